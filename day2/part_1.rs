@@ -1,11 +1,12 @@
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::usize;
 
+#[derive(Debug, Clone)]
 struct Roll {
-  red: usize,
-  green: usize,
-  blue: usize
+  cubes: BTreeMap<String, usize>
 }
 
 struct Game {
@@ -13,19 +14,35 @@ struct Game {
   rolls: Vec<Roll>
 }
 
-const MAX_AMOUNTS: Roll = Roll {
-  red: 12,
-  green: 13,
-  blue: 14
-};
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  Equal,
+  NotEqual
+}
+
+// Constraint over the maximum amount of cubes shown in a game, the field
+// is a colour name or "total" (all the cubes of a roll)
+#[derive(Debug)]
+enum Constraint {
+  Compare { field: String, operator: Operator, value: usize },
+  And(Box<Constraint>, Box<Constraint>),
+  Or(Box<Constraint>, Box<Constraint>)
+}
+
+const MAX_AMOUNTS: &str = "12 red, 13 green, 14 blue";
+
+// Amount of cubes of a colour in a roll (zero if the colour isn't present)
+fn cubes_of(roll: &Roll, colour: &str) -> usize {
+  *roll.cubes.get(colour).unwrap_or(&0)
+}
 
 // Parse a roll string into a roll struct
-fn parse_roll(roll: &String) -> Roll {
-  let mut result = Roll {
-    red: 0,
-    green: 0,
-    blue: 0
-  };
+fn parse_roll(roll: &String) -> Result<Roll, String> {
+  let mut result = Roll { cubes: BTreeMap::new() };
   let mut amount_token = true;
   let mut amount = 0usize;
 
@@ -36,28 +53,49 @@ fn parse_roll(roll: &String) -> Roll {
 
     if amount_token {
       amount_token = false;
-      amount = token.parse().unwrap();
+      amount = token.parse()
+        .map_err(|_| format!("Invalid amount of cubes '{}'", token))?;
     } else {
       amount_token = true;
-      match token {
-        "red" => result.red += amount,
-        "green" => result.green += amount,
-        "blue" => result.blue += amount,
-        _ => ()
-      }
+      *result.cubes.entry(token.to_string()).or_insert(0) += amount;
     }
   }
 
-  result
+  if !amount_token {
+    return Err(format!("Missing colour after amount {}", amount));
+  }
+
+  Ok(result)
 }
 
-// Parse text line to a game struct
-fn parse_game(line: &String) -> Game {
+// Checks that all the colours of the roll are in the colours list (if any)
+fn check_colours(roll: &Roll, colours: Option<&Vec<String>>) -> Result<(), String> {
+  let colours = match colours {
+    Some(colours) => colours,
+    None => return Ok(())
+  };
+
+  for colour in roll.cubes.keys() {
+    if !colours.contains(colour) {
+      return Err(format!("Unknown colour '{}'", colour));
+    }
+  }
+
+  Ok(())
+}
+
+// Parse text line to a game struct, the colours not included in the
+// colours list are reported as an error (without list any colour is valid)
+fn parse_game(line: &String, colours: Option<&Vec<String>>) -> Result<Game, String> {
   let mut game_token = false;
   let mut id = 0usize;
   let mut roll_token = String::new();
   let mut rolls: Vec<Roll> = Vec::new();
   for token in line.split([' ', ':']) {
+    if token.is_empty() {
+      continue;
+    }
+
     if token == "Game" {
       game_token = true;
       continue;
@@ -65,7 +103,8 @@ fn parse_game(line: &String) -> Game {
 
     if game_token {
       game_token = false;
-      id = token.parse().unwrap();
+      id = token.parse()
+        .map_err(|_| format!("Invalid game id '{}'", token))?;
       continue;
     }
 
@@ -73,33 +112,280 @@ fn parse_game(line: &String) -> Game {
     roll_token += " ";
 
     if token.contains(';') {
-      rolls.push(parse_roll(&roll_token));
+      let roll = parse_roll(&roll_token)?;
+      check_colours(&roll, colours)?;
+      if !roll.cubes.is_empty() {
+        rolls.push(roll);
+      }
       roll_token.clear();
     }
   }
 
   if !roll_token.is_empty() {
-    rolls.push(parse_roll(&roll_token));
+    let roll = parse_roll(&roll_token)?;
+    check_colours(&roll, colours)?;
+    if !roll.cubes.is_empty() {
+      rolls.push(roll);
+    }
+  }
+
+  Ok(Game { id, rolls })
+}
+
+// Colours printed first in the canonical format, the rest of the colours go
+// after them in alphabetical order
+const CANONICAL_COLOURS: [&str; 3] = ["red", "green", "blue"];
+
+// Print a roll in the canonical format "a red, b green, c blue"
+impl fmt::Display for Roll {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut colours: Vec<&String> = self.cubes.keys().collect();
+    colours.sort_by_key(|colour| {
+      let position = CANONICAL_COLOURS.iter().position(|canonical| canonical == colour);
+      (position.unwrap_or(CANONICAL_COLOURS.len()), colour.to_string())
+    });
+
+    let cubes: Vec<String> = colours.iter()
+      .map(|colour| format!("{} {}", self.cubes[*colour], colour))
+      .collect();
+    write!(f, "{}", cubes.join(", "))
   }
+}
 
-  Game { id, rolls }
+// Print a game in the canonical format "Game N: <roll>; <roll>; ..."
+impl fmt::Display for Game {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let rolls: Vec<String> = self.rolls.iter().map(|roll| roll.to_string()).collect();
+    write!(f, "Game {}: {}", self.id, rolls.join("; "))
+  }
+}
+
+// Rewrite a game log in the canonical format
+fn normalize_log(input: &String) -> Result<String, String> {
+  let mut output = String::new();
+  for (n, line) in input.lines().enumerate() {
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let game = parse_game(&line.to_string(), None)
+      .map_err(|error| format!("Error in line {}: {}", n + 1, error))?;
+    output += &game.to_string();
+    output += "\n";
+  }
+
+  Ok(output)
+}
+
+// Checks that parsing and printing a canonical game gives the same line,
+// returns the number of lines that were already canonical
+fn check_roundtrip(input: &String) -> Result<usize, String> {
+  for line in normalize_log(input)?.lines() {
+    let game = parse_game(&line.to_string(), None)?;
+    let printed = game.to_string();
+    if printed != line {
+      return Err(format!("Round trip mismatch:\n  {}\n  {}", line, printed));
+    }
+  }
+
+  let mut canonical_lines = 0usize;
+  for line in input.lines() {
+    if let Ok(game) = parse_game(&line.to_string(), None) {
+      if game.to_string() == line {
+        canonical_lines += 1;
+      }
+    }
+  }
+
+  Ok(canonical_lines)
 }
 
 // Return if the game is possible with certain amount of cubes
 fn is_possible(game: &Game, max_amounts: &Roll) -> bool {
   for roll in game.rolls.iter() {
-    if roll.red > max_amounts.red {
-      return false;
-    } else if roll.green > max_amounts.green {
-      return false;
-    } else if roll.blue > max_amounts.blue {
-      return false;
+    for (colour, amount) in roll.cubes.iter() {
+      if *amount > cubes_of(max_amounts, colour) {
+        return false;
+      }
     }
   }
 
   true
 }
 
+// Split a constraint expression into tokens
+fn tokenize_constraint(expression: &str) -> Result<Vec<String>, String> {
+  let chars: Vec<char> = expression.chars().collect();
+  let mut tokens: Vec<String> = Vec::new();
+  let mut i = 0usize;
+
+  while i < chars.len() {
+    let letter = chars[i];
+    if letter.is_whitespace() {
+      i += 1;
+      continue;
+    }
+
+    if letter == '(' || letter == ')' {
+      tokens.push(letter.to_string());
+      i += 1;
+      continue;
+    }
+
+    if letter.is_alphanumeric() || letter == '_' {
+      let begin = i;
+      while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+        i += 1;
+      }
+      tokens.push(chars[begin..i].iter().collect());
+      continue;
+    }
+
+    let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+    match pair.as_str() {
+      "&&" | "||" | "<=" | ">=" | "==" | "!=" => {
+        tokens.push(pair);
+        i += 2;
+      },
+      _ if letter == '<' || letter == '>' => {
+        tokens.push(letter.to_string());
+        i += 1;
+      },
+      _ => return Err(format!("Unexpected character '{}' at position {}", letter, i + 1))
+    }
+  }
+
+  Ok(tokens)
+}
+
+fn parse_operator(token: &str) -> Option<Operator> {
+  match token {
+    "<" => Some(Operator::Less),
+    "<=" => Some(Operator::LessEqual),
+    ">" => Some(Operator::Greater),
+    ">=" => Some(Operator::GreaterEqual),
+    "==" => Some(Operator::Equal),
+    "!=" => Some(Operator::NotEqual),
+    _ => None
+  }
+}
+
+// Parse a comparison or a parenthesized expression
+fn parse_comparison(tokens: &Vec<String>, pos: &mut usize) -> Result<Constraint, String> {
+  let field = match tokens.get(*pos) {
+    Some(token) => token.clone(),
+    None => return Err("Expected a comparison at the end of the expression".to_string())
+  };
+  *pos += 1;
+
+  if field == "(" {
+    let constraint = parse_or(tokens, pos)?;
+    if tokens.get(*pos).map(|token| token.as_str()) != Some(")") {
+      return Err("Expected ')'".to_string());
+    }
+    *pos += 1;
+    return Ok(constraint);
+  }
+
+  if !field.chars().all(|letter| letter.is_alphabetic() || letter == '_') {
+    return Err(format!("Expected a colour or 'total', found '{}'", field));
+  }
+
+  let operator = match tokens.get(*pos).and_then(|token| parse_operator(token)) {
+    Some(operator) => operator,
+    None => return Err(format!("Expected a comparison operator after '{}'", field))
+  };
+  *pos += 1;
+
+  let value = match tokens.get(*pos).and_then(|token| token.parse::<usize>().ok()) {
+    Some(value) => value,
+    None => return Err(format!("Expected an amount of cubes after '{}'", field))
+  };
+  *pos += 1;
+
+  Ok(Constraint::Compare { field, operator, value })
+}
+
+fn parse_and(tokens: &Vec<String>, pos: &mut usize) -> Result<Constraint, String> {
+  let mut constraint = parse_comparison(tokens, pos)?;
+  while tokens.get(*pos).map(|token| token.as_str()) == Some("&&") {
+    *pos += 1;
+    let right = parse_comparison(tokens, pos)?;
+    constraint = Constraint::And(Box::new(constraint), Box::new(right));
+  }
+
+  Ok(constraint)
+}
+
+fn parse_or(tokens: &Vec<String>, pos: &mut usize) -> Result<Constraint, String> {
+  let mut constraint = parse_and(tokens, pos)?;
+  while tokens.get(*pos).map(|token| token.as_str()) == Some("||") {
+    *pos += 1;
+    let right = parse_and(tokens, pos)?;
+    constraint = Constraint::Or(Box::new(constraint), Box::new(right));
+  }
+
+  Ok(constraint)
+}
+
+// Parse a constraint expression like "red<=12 && (blue<=14 || total<=30)"
+fn parse_constraint(expression: &str) -> Result<Constraint, String> {
+  let tokens = tokenize_constraint(expression)?;
+  let mut pos = 0usize;
+  let constraint = parse_or(&tokens, &mut pos)?;
+  if pos < tokens.len() {
+    return Err(format!("Unexpected '{}' in the expression", tokens[pos]));
+  }
+
+  Ok(constraint)
+}
+
+// Collect the colours used in a constraint
+fn constraint_colours(constraint: &Constraint, colours: &mut Vec<String>) {
+  match constraint {
+    Constraint::Compare { field, .. } => {
+      if field != "total" && !colours.contains(field) {
+        colours.push(field.clone());
+      }
+    },
+    Constraint::And(left, right) | Constraint::Or(left, right) => {
+      constraint_colours(left, colours);
+      constraint_colours(right, colours);
+    }
+  }
+}
+
+// Maximum amount of cubes of a field shown in any roll of the game, so
+// "red<=12" holds for every roll and "red>=5" for at least one
+fn max_field(game: &Game, field: &str) -> usize {
+  game.rolls.iter().map(|roll| {
+    if field == "total" {
+      roll.cubes.values().sum()
+    } else {
+      cubes_of(roll, field)
+    }
+  }).max().unwrap_or(0)
+}
+
+// Checks if a game satisfies a constraint
+fn satisfies(game: &Game, constraint: &Constraint) -> bool {
+  match constraint {
+    Constraint::Compare { field, operator, value } => {
+      let amount = max_field(game, field);
+      match operator {
+        Operator::Less => amount < *value,
+        Operator::LessEqual => amount <= *value,
+        Operator::Greater => amount > *value,
+        Operator::GreaterEqual => amount >= *value,
+        Operator::Equal => amount == *value,
+        Operator::NotEqual => amount != *value
+      }
+    },
+    Constraint::And(left, right) => satisfies(game, left) && satisfies(game, right),
+    Constraint::Or(left, right) => satisfies(game, left) || satisfies(game, right)
+  }
+}
+
 fn main() {
   let args: Vec<String> = env::args().collect();
   if args.len() < 2 {
@@ -111,10 +397,91 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
 
+  // "--normalize [output]" rewrites the log in the canonical format
+  if args.len() > 2 && args[2] == "--normalize" {
+    let output = match normalize_log(&input) {
+      Ok(output) => output,
+      Err(error) => {
+        println!("{}", error);
+        return;
+      }
+    };
+
+    if args.len() > 3 {
+      fs::write(&args[3], output).expect("Error writing the file");
+    } else {
+      print!("{}", output);
+    }
+    return;
+  }
+
+  // "--check-roundtrip" checks that printing is the inverse of parsing
+  if args.len() > 2 && args[2] == "--check-roundtrip" {
+    match check_roundtrip(&input) {
+      Ok(canonical_lines) => {
+        println!("Round trip ok ({} of {} lines already canonical)", canonical_lines, input.lines().count());
+      },
+      Err(error) => {
+        println!("{}", error);
+        std::process::exit(1);
+      }
+    }
+    return;
+  }
+
+  // A constraint query can be given with "--query <expression>"
+  let mut constraint: Option<Constraint> = None;
+  if args.len() > 2 && args[2] == "--query" {
+    if args.len() < 4 {
+      println!("Missing the query expression");
+      return;
+    }
+
+    constraint = match parse_constraint(&args[3]) {
+      Ok(constraint) => Some(constraint),
+      Err(error) => {
+        println!("Invalid query '{}': {}", args[3], error);
+        return;
+      }
+    };
+  }
+
+  // The bag can be given as the second argument, e.g. "12 red, 3 yellow"
+  let bag_str = if args.len() > 2 && constraint.is_none() {
+    args[2].clone()
+  } else {
+    MAX_AMOUNTS.to_string()
+  };
+  let max_amounts = match parse_roll(&bag_str) {
+    Ok(bag) => bag,
+    Err(error) => {
+      println!("Invalid bag: {}", error);
+      return;
+    }
+  };
+  let mut colours: Vec<String> = max_amounts.cubes.keys().cloned().collect();
+  if let Some(constraint) = &constraint {
+    constraint_colours(constraint, &mut colours);
+  }
+
+  let mut matching: Vec<usize> = Vec::new();
   let mut id_sum = 0usize;
-  for line in input.lines() {
-    let game = parse_game(&line.to_string());
-    let possible = is_possible(&game, &MAX_AMOUNTS);
+  for (n, line) in input.lines().enumerate() {
+    let game = match parse_game(&line.to_string(), Some(&colours)) {
+      Ok(game) => game,
+      Err(error) => {
+        println!("Error in line {}: {}", n + 1, error);
+        return;
+      }
+    };
+    if let Some(constraint) = &constraint {
+      if satisfies(&game, constraint) {
+        matching.push(game.id);
+      }
+      continue;
+    }
+
+    let possible = is_possible(&game, &max_amounts);
 
     println!("Game {} {}", game.id, possible);
 
@@ -124,5 +491,41 @@ fn main() {
     id_sum += game.id;
   }
 
+  if constraint.is_some() {
+    let ids: Vec<String> = matching.iter().map(|id| id.to_string()).collect();
+    println!("Matching games: {}", ids.join(", "));
+    println!("Sum of matching games {}", matching.iter().sum::<usize>());
+    return;
+  }
+
   println!("Sum of possible games {}", id_sum);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn roundtrip_of_canonical_lines() {
+    let lines = [
+      "Game 1: 7 red, 2 green, 6 blue; 3 red, 12 green, 6 blue",
+      "Game 2: 4 red, 10 green; 2 red; 11 red, 12 green, 1 blue",
+      "Game 3: 1 green; 15 red, 1 green, 7 blue",
+      "Game 4: 1 red, 2 blue, 3 purple, 4 yellow",
+      "Game 5: "
+    ];
+
+    for line in lines {
+      let game = parse_game(&line.to_string(), None).unwrap();
+      assert_eq!(game.to_string(), line);
+    }
+  }
+
+  #[test]
+  fn normalize_messy_lines() {
+    let input = "Game 7:  3 blue,2 red ,  1 blue; 4 green\n\nGame 8: 5 green, 1 red;\n".to_string();
+    let output = normalize_log(&input).unwrap();
+    assert_eq!(output, "Game 7: 2 red, 4 blue; 4 green\nGame 8: 1 red, 5 green\n");
+    assert_eq!(normalize_log(&output).unwrap(), output);
+  }
+}
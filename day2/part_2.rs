@@ -1,12 +1,11 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::usize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Roll {
-  red: usize,
-  green: usize,
-  blue: usize
+  cubes: BTreeMap<String, usize>
 }
 
 struct Game {
@@ -14,19 +13,18 @@ struct Game {
   rolls: Vec<Roll>
 }
 
-// const MAX_AMOUNTS: Roll = Roll {
-//   red: 12,
-//   green: 13,
-//   blue: 14
-// };
+const MAX_AMOUNTS: &str = "12 red, 13 green, 14 blue";
+
+const DEFAULT_COLOURS: &str = "red,green,blue";
+
+// Amount of cubes of a colour in a roll (zero if the colour isn't present)
+fn cubes_of(roll: &Roll, colour: &str) -> usize {
+  *roll.cubes.get(colour).unwrap_or(&0)
+}
 
 // Parse a roll string into a roll struct
-fn parse_roll(roll: &String) -> Roll {
-  let mut result = Roll {
-    red: 0,
-    green: 0,
-    blue: 0
-  };
+fn parse_roll(roll: &String) -> Result<Roll, String> {
+  let mut result = Roll { cubes: BTreeMap::new() };
   let mut amount_token = true;
   let mut amount = 0usize;
 
@@ -37,28 +35,44 @@ fn parse_roll(roll: &String) -> Roll {
 
     if amount_token {
       amount_token = false;
-      amount = token.parse().unwrap();
+      amount = token.parse()
+        .map_err(|_| format!("Invalid amount of cubes '{}'", token))?;
     } else {
       amount_token = true;
-      match token {
-        "red" => result.red += amount,
-        "green" => result.green += amount,
-        "blue" => result.blue += amount,
-        _ => ()
-      }
+      *result.cubes.entry(token.to_string()).or_insert(0) += amount;
     }
   }
 
-  result
+  if !amount_token {
+    return Err(format!("Missing colour after amount {}", amount));
+  }
+
+  Ok(result)
 }
 
-// Parse text line to a game struct
-fn parse_game(line: &String) -> Game {
+// Checks that all the colours of the roll are in the colours list
+fn check_colours(roll: &Roll, colours: &Vec<String>) -> Result<(), String> {
+  for colour in roll.cubes.keys() {
+    if !colours.contains(colour) {
+      return Err(format!("Unknown colour '{}'", colour));
+    }
+  }
+
+  Ok(())
+}
+
+// Parse text line to a game struct, the colours not included in the
+// colours list are reported as an error
+fn parse_game(line: &String, colours: &Vec<String>) -> Result<Game, String> {
   let mut game_token = false;
   let mut id = 0usize;
   let mut roll_token = String::new();
   let mut rolls: Vec<Roll> = Vec::new();
   for token in line.split([' ', ':']) {
+    if token.is_empty() {
+      continue;
+    }
+
     if token == "Game" {
       game_token = true;
       continue;
@@ -66,7 +80,8 @@ fn parse_game(line: &String) -> Game {
 
     if game_token {
       game_token = false;
-      id = token.parse().unwrap();
+      id = token.parse()
+        .map_err(|_| format!("Invalid game id '{}'", token))?;
       continue;
     }
 
@@ -74,56 +89,314 @@ fn parse_game(line: &String) -> Game {
     roll_token += " ";
 
     if token.contains(';') {
-      rolls.push(parse_roll(&roll_token));
+      let roll = parse_roll(&roll_token)?;
+      check_colours(&roll, colours)?;
+      if !roll.cubes.is_empty() {
+        rolls.push(roll);
+      }
       roll_token.clear();
     }
   }
 
   if !roll_token.is_empty() {
-    rolls.push(parse_roll(&roll_token));
+    let roll = parse_roll(&roll_token)?;
+    check_colours(&roll, colours)?;
+    if !roll.cubes.is_empty() {
+      rolls.push(roll);
+    }
   }
 
-  Game { id, rolls }
+  Ok(Game { id, rolls })
 }
 
 // Return if the game is possible with certain amount of cubes
-// fn is_possible(game: &Game, max_amounts: &Roll) -> bool {
-//   for roll in game.rolls.iter() {
-//     if roll.red > max_amounts.red {
-//       return false;
-//     } else if roll.green > max_amounts.green {
-//       return false;
-//     } else if roll.blue > max_amounts.blue {
-//       return false;
-//     }
-//   }
-//
-//   true
-// }
-
-// Calculates the power of a game
-fn game_power(game: &Game) -> usize {
-  let mut min_cubes = Roll {
-    red: 0,
-    green: 0,
-    blue: 0
-  };
+fn is_possible(game: &Game, max_amounts: &Roll) -> bool {
+  for roll in game.rolls.iter() {
+    for (colour, amount) in roll.cubes.iter() {
+      if *amount > cubes_of(max_amounts, colour) {
+        return false;
+      }
+    }
+  }
+
+  true
+}
+
+// Calculates the minimum amount of cubes of each colour needed for a game
+fn min_cubes(game: &Game) -> Roll {
+  let mut result = Roll { cubes: BTreeMap::new() };
 
   for roll in game.rolls.iter() {
-    if roll.red > min_cubes.red {
-      min_cubes.red = roll.red;
+    for (colour, amount) in roll.cubes.iter() {
+      let min_amount = result.cubes.entry(colour.clone()).or_insert(0);
+      if *amount > *min_amount {
+        *min_amount = *amount;
+      }
+    }
+  }
+
+  result
+}
+
+// Calculates the power of a game, the product of the minimum amount of
+// cubes of every colour (a missing colour makes the power zero)
+fn game_power(game: &Game, colours: &Vec<String>) -> usize {
+  let min_cubes = min_cubes(game);
+
+  colours.iter().map(|colour| cubes_of(&min_cubes, colour)).product()
+}
+
+// Statistics of a game for the report
+struct GameStats {
+  id: usize,
+  rolls: usize,
+  max_cubes: Vec<usize>,
+  total_cubes: Vec<usize>,
+  min_bag: usize,
+  power: usize,
+  feasible: bool
+}
+
+fn game_stats(game: &Game, colours: &Vec<String>, bag: &Roll) -> GameStats {
+  let min_cubes = min_cubes(game);
+  let total_cubes = colours.iter()
+    .map(|colour| game.rolls.iter().map(|roll| cubes_of(roll, colour)).sum())
+    .collect();
+
+  GameStats {
+    id: game.id,
+    rolls: game.rolls.len(),
+    max_cubes: colours.iter().map(|colour| cubes_of(&min_cubes, colour)).collect(),
+    total_cubes,
+    min_bag: min_cubes.cubes.values().sum(),
+    power: game_power(game, colours),
+    feasible: is_possible(game, bag)
+  }
+}
+
+// Write one CSV row per game
+fn write_report(stats: &Vec<GameStats>, colours: &Vec<String>) -> String {
+  let mut csv = String::from("id,rolls");
+  for colour in colours {
+    csv += &format!(",{}_max,{}_total", colour, colour);
+  }
+  csv += ",min_bag,power,feasible\n";
+
+  for game in stats {
+    csv += &format!("{},{}", game.id, game.rolls);
+    for i in 0..colours.len() {
+      csv += &format!(",{},{}", game.max_cubes[i], game.total_cubes[i]);
+    }
+    csv += &format!(",{},{},{}\n", game.min_bag, game.power, game.feasible);
+  }
+
+  csv
+}
+
+// Print the minimum, mean, median and maximum of some values
+fn print_distribution(name: &str, values: &Vec<usize>) {
+  if values.is_empty() {
+    return;
+  }
+
+  let mut sorted = values.clone();
+  sorted.sort();
+  let mean = sorted.iter().sum::<usize>() as f64 / sorted.len() as f64;
+  println!("{}: min {}, mean {:.2}, median {}, max {}",
+    name, sorted[0], mean, sorted[sorted.len() / 2], sorted[sorted.len() - 1]);
+}
+
+// Print summary statistics of the games
+fn print_summary(stats: &Vec<GameStats>, colours: &Vec<String>) {
+  let feasible = stats.iter().filter(|game| game.feasible).count();
+  println!("Games: {} ({} feasible)", stats.len(), feasible);
+  print_distribution("Rolls", &stats.iter().map(|game| game.rolls).collect());
+  for (i, colour) in colours.iter().enumerate() {
+    print_distribution(&format!("Max {}", colour), &stats.iter().map(|game| game.max_cubes[i]).collect());
+  }
+  print_distribution("Min bag", &stats.iter().map(|game| game.min_bag).collect());
+  print_distribution("Power", &stats.iter().map(|game| game.power).collect());
+
+  let mut heaviest: Vec<&GameStats> = stats.iter().collect();
+  heaviest.sort_by(|a, b| b.min_bag.cmp(&a.min_bag).then(b.power.cmp(&a.power)));
+  println!("Heaviest games:");
+  for game in heaviest.iter().take(5) {
+    println!("  Game {}: min bag {}, power {}", game.id, game.min_bag, game.power);
+  }
+}
+
+// Bag of cubes with the amount of each colour in the colours list order
+#[derive(Debug, Clone)]
+struct BagEstimate {
+  bag: Vec<usize>,
+  log_likelihood: f64,
+  probability: f64
+}
+
+// Table of ln(n!) for 0..=max
+fn ln_factorials(max: usize) -> Vec<f64> {
+  let mut table: Vec<f64> = Vec::with_capacity(max + 1);
+  table.push(0.0);
+  for n in 1..=max {
+    let previous = table[n - 1];
+    table.push(previous + (n as f64).ln());
+  }
+
+  table
+}
+
+// ln of the binomial coefficient C(n, k)
+fn ln_choose(ln_fact: &Vec<f64>, n: usize, k: usize) -> f64 {
+  if k > n {
+    return f64::NEG_INFINITY;
+  }
+
+  ln_fact[n] - ln_fact[k] - ln_fact[n - k]
+}
+
+// ln of the probability of drawing the roll without replacement from the
+// bag (multivariate hypergeometric distribution)
+fn roll_log_likelihood(roll: &Roll, bag: &Vec<usize>, colours: &Vec<String>, ln_fact: &Vec<f64>) -> f64 {
+  let bag_total: usize = bag.iter().sum();
+  let roll_total: usize = colours.iter().map(|colour| cubes_of(roll, colour)).sum();
+  if roll_total > bag_total {
+    return f64::NEG_INFINITY;
+  }
+
+  let mut result = -ln_choose(ln_fact, bag_total, roll_total);
+  for (i, colour) in colours.iter().enumerate() {
+    result += ln_choose(ln_fact, bag[i], cubes_of(roll, colour));
+  }
+
+  result
+}
+
+// ln of the probability of a whole game, the cubes are put back in the bag
+// after every roll
+fn game_log_likelihood(game: &Game, bag: &Vec<usize>, colours: &Vec<String>, ln_fact: &Vec<f64>) -> f64 {
+  game.rolls.iter()
+    .map(|roll| roll_log_likelihood(roll, bag, colours, ln_fact))
+    .sum()
+}
+
+// First bag with min cubes of each colour
+fn first_bag(n_colours: usize, min: usize) -> Vec<usize> {
+  vec![min; n_colours]
+}
+
+// Moves to the next bag with between min and max cubes of each colour (like
+// an odometer), the bags are generated one by one instead of all at once
+// because there are (max - min + 1)^colours of them
+fn next_bag(bag: &mut Vec<usize>, min: usize, max: usize) -> bool {
+  for amount in bag.iter_mut().rev() {
+    if *amount < max {
+      *amount += 1;
+      return true;
     }
+    *amount = min;
+  }
+
+  false
+}
+
+// Top bags by likelihood of a stream of bags, the probabilities are normalized
+// over all the bags of the stream (uniform prior) with a running log-sum-exp
+#[derive(Debug, Clone)]
+struct BagRanking {
+  top: usize,
+  best: Vec<BagEstimate>,
+  max_ll: f64,
+  scaled_total: f64
+}
+
+fn new_ranking(top: usize) -> BagRanking {
+  BagRanking { top, best: Vec::new(), max_ll: f64::NEG_INFINITY, scaled_total: 0.0 }
+}
+
+fn add_to_ranking(ranking: &mut BagRanking, bag: &Vec<usize>, ll: f64) {
+  if ll == f64::NEG_INFINITY {
+    return;
+  }
+
+  if ll > ranking.max_ll {
+    ranking.scaled_total = ranking.scaled_total * (ranking.max_ll - ll).exp() + 1.0;
+    ranking.max_ll = ll;
+  } else {
+    ranking.scaled_total += (ll - ranking.max_ll).exp();
+  }
+
+  // The best list is sorted by likelihood, the first bag wins the ties
+  let position = ranking.best.partition_point(|estimate| estimate.log_likelihood >= ll);
+  if position < ranking.top {
+    ranking.best.insert(position, BagEstimate { bag: bag.clone(), log_likelihood: ll, probability: 0.0 });
+    ranking.best.truncate(ranking.top);
+  }
+}
+
+fn rank_bags(ranking: &BagRanking) -> Vec<BagEstimate> {
+  ranking.best.iter()
+    .map(|estimate| BagEstimate {
+      probability: (estimate.log_likelihood - ranking.max_ll).exp() / ranking.scaled_total,
+      ..estimate.clone()
+    })
+    .collect()
+}
+
+fn print_estimates(estimates: &Vec<BagEstimate>, colours: &Vec<String>) {
+  if estimates.is_empty() {
+    println!("  No bag in the range can produce this");
+    return;
+  }
+
+  for estimate in estimates {
+    let bag: Vec<String> = estimate.bag.iter().zip(colours.iter())
+      .map(|(amount, colour)| format!("{} {}", amount, colour))
+      .collect();
+    println!("  {}: likelihood {:.4e} (ln {:.3}), probability {:.4}",
+      bag.join(", "), estimate.log_likelihood.exp(), estimate.log_likelihood, estimate.probability);
+  }
+}
+
+// Rank the most likely bags for every game and for all the games together
+fn estimate_bags(games: &Vec<Game>, colours: &Vec<String>, min: usize, max: usize, top: usize) {
+  let ln_fact = ln_factorials(max * colours.len());
+  let mut rankings: Vec<BagRanking> = vec![new_ranking(top); games.len()];
+  let mut global_ranking = new_ranking(top);
 
-    if roll.green > min_cubes.green {
-      min_cubes.green = roll.green;
+  let mut bag = first_bag(colours.len(), min);
+  loop {
+    let mut global_ll = 0.0;
+    for (game, ranking) in games.iter().zip(rankings.iter_mut()) {
+      let ll = game_log_likelihood(game, &bag, colours, &ln_fact);
+      add_to_ranking(ranking, &bag, ll);
+      global_ll += ll;
     }
+    add_to_ranking(&mut global_ranking, &bag, global_ll);
 
-    if roll.blue > min_cubes.blue {
-      min_cubes.blue = roll.blue;
+    if !next_bag(&mut bag, min, max) {
+      break;
     }
   }
 
-  min_cubes.red * min_cubes.green * min_cubes.blue
+  for (game, ranking) in games.iter().zip(rankings.iter()) {
+    println!("Game {}:", game.id);
+    print_estimates(&rank_bags(ranking), colours);
+  }
+
+  println!("All games:");
+  print_estimates(&rank_bags(&global_ranking), colours);
+}
+
+// Parse a "min..max" range of cubes (both included)
+fn parse_cube_range(range: &str) -> Option<(usize, usize)> {
+  let (min, max) = range.split_once("..")?;
+  let min: usize = min.parse().ok()?;
+  let max: usize = max.parse().ok()?;
+  if min > max {
+    return None;
+  }
+
+  Some((min, max))
 }
 
 fn main() {
@@ -137,10 +410,103 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
 
+  // The colours can be given as the second argument, e.g. "red,green,blue,yellow"
+  // "--estimate <min>..<max> [top]" ranks the most likely bags and
+  // "--report <file.csv> [--bag <roll>]" writes the statistics of every game
+  let mut colours_str = DEFAULT_COLOURS;
+  let mut estimate: Option<(usize, usize, usize)> = None;
+  let mut report: Option<String> = None;
+  let mut bag_str = MAX_AMOUNTS;
+  let mut i = 2usize;
+  while i < args.len() {
+    if args[i] == "--report" || args[i] == "--bag" {
+      let value = match args.get(i + 1) {
+        Some(value) => value,
+        None => {
+          println!("Missing the value of {}", args[i]);
+          return;
+        }
+      };
+      if args[i] == "--report" {
+        report = Some(value.clone());
+      } else {
+        bag_str = value.as_str();
+      }
+      i += 2;
+      continue;
+    }
+
+    if args[i].starts_with("--") && args[i] != "--estimate" {
+      println!("Unknown option '{}'", args[i]);
+      return;
+    }
+
+    if args[i] != "--estimate" {
+      colours_str = args[i].as_str();
+      i += 1;
+      continue;
+    }
+
+    let range = args.get(i + 1).and_then(|range| parse_cube_range(range));
+    let (min, max) = match range {
+      Some(range) => range,
+      None => {
+        println!("Expected a range of cubes like 0..20 after --estimate");
+        return;
+      }
+    };
+    i += 2;
+
+    let mut top = 3usize;
+    if let Some(n) = args.get(i).and_then(|n| n.parse().ok()) {
+      top = n;
+      i += 1;
+    }
+    estimate = Some((min, max, top));
+  }
+
+  let colours: Vec<String> = colours_str
+    .split([' ', ','])
+    .filter(|colour| !colour.is_empty())
+    .map(|colour| colour.to_string())
+    .collect();
+
+  let mut games: Vec<Game> = Vec::new();
+  for (n, line) in input.lines().enumerate() {
+    match parse_game(&line.to_string(), &colours) {
+      Ok(game) => games.push(game),
+      Err(error) => {
+        println!("Error in line {}: {}", n + 1, error);
+        return;
+      }
+    }
+  }
+
+  if let Some(report_file) = report {
+    let bag = match parse_roll(&bag_str.to_string()) {
+      Ok(bag) => bag,
+      Err(error) => {
+        println!("Invalid bag: {}", error);
+        return;
+      }
+    };
+    let stats: Vec<GameStats> = games.iter()
+      .map(|game| game_stats(game, &colours, &bag))
+      .collect();
+    fs::write(&report_file, write_report(&stats, &colours))
+      .expect("Error writing the file");
+    print_summary(&stats, &colours);
+    return;
+  }
+
+  if let Some((min, max, top)) = estimate {
+    estimate_bags(&games, &colours, min, max, top);
+    return;
+  }
+
   let mut power_sum = 0usize;
-  for line in input.lines() {
-    let game = parse_game(&line.to_string());
-    let power = game_power(&game);
+  for game in games.iter() {
+    let power = game_power(game, &colours);
     power_sum += power;
 
     println!("Game {} power {}", game.id, power);
@@ -1,9 +1,10 @@
-use std::collections::LinkedList;
 use std::env;
 use std::fs;
 use std::u64;
 use std::usize;
 
+// The columns (begin, end and pos) are counted in chars, not in bytes, so
+// schematics with Unicode symbols keep the same coordinates in every row
 #[derive(Clone)]
 struct EngineNumber {
   row: usize,
@@ -18,83 +19,310 @@ struct EngineSymbol {
   symbol: char
 }
 
+// Bipartite graph of numbers and symbols, the edges are pairs of indexes
+// (number, symbol) in the engine lists
+struct AdyacencyGraph {
+  numbers: Vec<usize>,
+  symbols: Vec<usize>,
+  edges: Vec<(usize, usize)>
+}
+
+// How many adyacent numbers a symbol needs to be a gear
+#[derive(Debug, Clone, Copy)]
+enum NumberCount {
+  Exactly(usize),
+  AtLeast(usize),
+  Between(usize, usize)
+}
+
+// Symbols that are gears in the exported graph, the same rule as the part 2
+// without the aggregate of the numbers
+#[derive(Debug, Clone)]
+struct GearRule {
+  symbols: Vec<char>,
+  count: NumberCount
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum GraphFilter {
+  All,
+  Gears,
+  Unattached
+}
+
+// Cells around a symbol where a number touches it, the radius versions use
+// the Chebyshev (square) or Manhattan (diamond) distance
+#[derive(Debug, Clone, Copy)]
+enum Neighbourhood {
+  Orthogonal,
+  EightConnected,
+  Chebyshev(usize),
+  Manhattan(usize)
+}
+
+// Grid of cells with the index of the number that fills each cell
+struct NumberIndex {
+  rows: Vec<Vec<Option<usize>>>
+}
+
 fn is_digit(letter: char) -> bool {
   letter >= '0' && letter <= '9'
 }
 
+// Parse a number that ends just before the column next (the digits are
+// ASCII, so the length in chars is the same as in bytes)
+fn parse_number(row: usize, next: usize, number_str: &String) -> EngineNumber {
+  let number: u64 = number_str.parse().unwrap();
+  let begin = next - number_str.len();
+  let end = next - 1;
+
+  EngineNumber { row, begin, end, number }
+}
+
 // Parse the input to two list, one of engine numbers and other of engine symbols
-fn parse_engine(input: &String) -> (LinkedList<EngineNumber>, LinkedList<EngineSymbol>) {
-  let mut numbers: LinkedList<EngineNumber> = LinkedList::new();
-  let mut symbols: LinkedList<EngineSymbol> = LinkedList::new();
+fn parse_engine(input: &String) -> (Vec<EngineNumber>, Vec<EngineSymbol>) {
+  let mut numbers: Vec<EngineNumber> = Vec::new();
+  let mut symbols: Vec<EngineSymbol> = Vec::new();
 
   for (row, line) in input.lines().enumerate() {
     let mut number_str = String::new();
+    let mut width = 0usize;
     for (i, cell) in line.chars().enumerate() {
+      width = i + 1;
       if is_digit(cell) {
         number_str.push(cell);
         continue;
       }
 
       if !number_str.is_empty() {
-        let number: u64 = number_str.parse().unwrap();
-        let begin = i - number_str.len();
-        let end = i - 1;
+        numbers.push(parse_number(row, i, &number_str));
         number_str.clear();
-        numbers.push_back(EngineNumber { row, begin, end, number });
       }
      
       if cell == '.' {
         continue;
       }
 
-      symbols.push_back(EngineSymbol { row, pos: i, symbol: cell });
+      symbols.push(EngineSymbol { row, pos: i, symbol: cell });
     }
 
     if !number_str.is_empty() {
-      let number: u64 = number_str.parse().unwrap();
-      let begin = line.len() - number_str.len();
-      let end = line.len() - 1;
-      number_str.clear();
-      numbers.push_back(EngineNumber { row, begin, end, number });
+      numbers.push(parse_number(row, width, &number_str));
     }
   }
 
   (numbers, symbols)
 }
 
-// Checks if a number is adyacent to a symbol
-fn is_adyacent(number: &EngineNumber, symbol: &EngineSymbol) -> bool {
-  let begin = if number.begin == 0 {
-      number.begin
-    } else {
-      number.begin - 1
-    };
+// Build the cell index of the numbers
+fn build_index(numbers: &Vec<EngineNumber>) -> NumberIndex {
+  let mut rows: Vec<Vec<Option<usize>>> = Vec::new();
+  for (id, number) in numbers.iter().enumerate() {
+    if rows.len() <= number.row {
+      rows.resize(number.row + 1, Vec::new());
+    }
+
+    let cells = &mut rows[number.row];
+    if cells.len() <= number.end {
+      cells.resize(number.end + 1, None);
+    }
 
-  if number.row == symbol.row {
-    (number.end + 1) == symbol.pos || begin == symbol.pos
-  } else if number.row.abs_diff(symbol.row) == 1 {
-    symbol.pos <= (number.end + 1) && symbol.pos >= begin
-  } else {
-    false
+    for cell in cells[number.begin..=number.end].iter_mut() {
+      *cell = Some(id);
+    }
   }
+
+  NumberIndex { rows }
+}
+
+// Parse a neighbourhood like "orthogonal", "8", "chebyshev:2" or "manhattan:3"
+fn parse_neighbourhood(neighbourhood: &str) -> Option<Neighbourhood> {
+  match neighbourhood.split_once(':') {
+    Some(("chebyshev", radius)) => Some(Neighbourhood::Chebyshev(radius.parse().ok()?)),
+    Some(("manhattan", radius)) => Some(Neighbourhood::Manhattan(radius.parse().ok()?)),
+    Some(_) => None,
+    None => match neighbourhood {
+      "orthogonal" => Some(Neighbourhood::Orthogonal),
+      "8" => Some(Neighbourhood::EightConnected),
+      _ => None
+    }
+  }
+}
+
+// Indexes of the numbers adyacent to a symbol in ascending order, only the
+// cells of the neighbourhood inside the grid are visited (the radius can be
+// larger than the grid)
+fn adyacent_numbers(index: &NumberIndex, symbol: &EngineSymbol, neighbourhood: Neighbourhood) -> Vec<usize> {
+  let (radius, manhattan) = match neighbourhood {
+    Neighbourhood::Orthogonal => (1, true),
+    Neighbourhood::EightConnected => (1, false),
+    Neighbourhood::Chebyshev(radius) => (radius, false),
+    Neighbourhood::Manhattan(radius) => (radius, true)
+  };
+
+  let mut result: Vec<usize> = Vec::new();
+  let first_row = symbol.row.saturating_sub(radius);
+  let end_row = symbol.row.saturating_add(radius).saturating_add(1).min(index.rows.len());
+  for row in first_row..end_row {
+    let reach = if manhattan { radius - row.abs_diff(symbol.row) } else { radius };
+    let cells = &index.rows[row];
+    let first_pos = symbol.pos.saturating_sub(reach);
+    let end_pos = symbol.pos.saturating_add(reach).saturating_add(1).min(cells.len());
+    for id in cells[first_pos.min(end_pos)..end_pos].iter().flatten() {
+      result.push(*id);
+    }
+  }
+
+  result.sort();
+  result.dedup();
+  result
 }
 
 // Filters the numbers that are adyacent to one or more symbols in the list
-fn calculate_adyacent(numbers: &LinkedList<EngineNumber>, symbols: &LinkedList<EngineSymbol>) -> LinkedList<EngineNumber> {
-  let mut result: LinkedList<EngineNumber> = LinkedList::new();
-  for number in numbers {
-    for symbol in symbols {
-      if is_adyacent(number, symbol) {
-        println!("{} adyacent to {}", number.number, symbol.symbol);
-        result.push_back(number.clone());
-        break;
+fn calculate_adyacent(numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>, neighbourhood: Neighbourhood) -> Vec<EngineNumber> {
+  let index = build_index(numbers);
+  let mut adyacent_symbol: Vec<Option<char>> = vec![None; numbers.len()];
+  for symbol in symbols {
+    for id in adyacent_numbers(&index, symbol, neighbourhood) {
+      if adyacent_symbol[id].is_none() {
+        adyacent_symbol[id] = Some(symbol.symbol);
       }
     }
   }
 
+  let mut result: Vec<EngineNumber> = Vec::new();
+  for (number, symbol) in numbers.iter().zip(adyacent_symbol) {
+    if let Some(symbol) = symbol {
+      println!("{} adyacent to {}", number.number, symbol);
+      result.push(number.clone());
+    }
+  }
+
   result
 }
 
+// Parse a count like "2" (exactly), "2+" (at least) or "2..4" (both included)
+fn parse_count(count: &str) -> Option<NumberCount> {
+  if let Some(min) = count.strip_suffix('+') {
+    return Some(NumberCount::AtLeast(min.parse().ok()?));
+  }
+
+  if let Some((min, max)) = count.split_once("..") {
+    let min: usize = min.parse().ok()?;
+    let max: usize = max.parse().ok()?;
+    if min > max {
+      return None;
+    }
+    return Some(NumberCount::Between(min, max));
+  }
+
+  Some(NumberCount::Exactly(count.parse().ok()?))
+}
+
+fn count_matches(count: NumberCount, n: usize) -> bool {
+  match count {
+    NumberCount::Exactly(exact) => n == exact,
+    NumberCount::AtLeast(min) => n >= min,
+    NumberCount::Between(min, max) => n >= min && n <= max
+  }
+}
+
+// Build the adyacency graph keeping the nodes selected by the filter, the
+// gears follow the gear rule (a '*' adyacent to exactly two numbers by default)
+fn build_graph(numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>, filter: GraphFilter, rule: &GearRule, neighbourhood: Neighbourhood) -> AdyacencyGraph {
+  let index = build_index(numbers);
+  let mut graph = AdyacencyGraph {
+    numbers: Vec::new(),
+    symbols: Vec::new(),
+    edges: Vec::new()
+  };
+  let mut attached: Vec<bool> = vec![false; numbers.len()];
+
+  for (symbol_id, symbol) in symbols.iter().enumerate() {
+    let adyacents = adyacent_numbers(&index, symbol, neighbourhood);
+    for id in adyacents.iter() {
+      attached[*id] = true;
+    }
+
+    let is_gear = rule.symbols.contains(&symbol.symbol) && count_matches(rule.count, adyacents.len());
+    if filter == GraphFilter::Unattached || (filter == GraphFilter::Gears && !is_gear) {
+      continue;
+    }
+
+    graph.symbols.push(symbol_id);
+    for id in adyacents {
+      graph.edges.push((id, symbol_id));
+    }
+  }
+
+  graph.numbers = match filter {
+    GraphFilter::All => (0..numbers.len()).collect(),
+    GraphFilter::Gears => {
+      let mut gear_numbers: Vec<usize> = graph.edges.iter().map(|(id, _)| *id).collect();
+      gear_numbers.sort();
+      gear_numbers.dedup();
+      gear_numbers
+    },
+    GraphFilter::Unattached => (0..numbers.len()).filter(|id| !attached[*id]).collect()
+  };
+
+  graph
+}
+
+// Escape a symbol to be used inside a quoted string
+fn escape_symbol(symbol: char) -> String {
+  match symbol {
+    '"' => "\\\"".to_string(),
+    '\\' => "\\\\".to_string(),
+    _ => symbol.to_string()
+  }
+}
+
+// Graphviz DOT representation of the graph
+fn graph_to_dot(graph: &AdyacencyGraph, numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>) -> String {
+  let mut dot = String::from("graph engine {\n");
+  for id in graph.numbers.iter() {
+    let number = &numbers[*id];
+    dot += &format!("  n{} [shape=box, label=\"{}\\nrow {}, col {}-{}\"];\n",
+      id, number.number, number.row, number.begin, number.end);
+  }
+
+  for id in graph.symbols.iter() {
+    let symbol = &symbols[*id];
+    dot += &format!("  s{} [shape=circle, label=\"{}\\nrow {}, col {}\"];\n",
+      id, escape_symbol(symbol.symbol), symbol.row, symbol.pos);
+  }
+
+  for (number_id, symbol_id) in graph.edges.iter() {
+    dot += &format!("  n{} -- s{};\n", number_id, symbol_id);
+  }
+  dot += "}\n";
+
+  dot
+}
+
+// JSON representation of the graph
+fn graph_to_json(graph: &AdyacencyGraph, numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>) -> String {
+  let number_nodes: Vec<String> = graph.numbers.iter().map(|id| {
+    let number = &numbers[*id];
+    format!("    {{\"id\": {}, \"number\": {}, \"row\": {}, \"begin\": {}, \"end\": {}}}",
+      id, number.number, number.row, number.begin, number.end)
+  }).collect();
+
+  let symbol_nodes: Vec<String> = graph.symbols.iter().map(|id| {
+    let symbol = &symbols[*id];
+    format!("    {{\"id\": {}, \"symbol\": \"{}\", \"row\": {}, \"pos\": {}}}",
+      id, escape_symbol(symbol.symbol), symbol.row, symbol.pos)
+  }).collect();
+
+  let edges: Vec<String> = graph.edges.iter()
+    .map(|(number_id, symbol_id)| format!("    {{\"number\": {}, \"symbol\": {}}}", number_id, symbol_id))
+    .collect();
+
+  format!("{{\n  \"numbers\": [\n{}\n  ],\n  \"symbols\": [\n{}\n  ],\n  \"edges\": [\n{}\n  ]\n}}\n",
+    number_nodes.join(",\n"), symbol_nodes.join(",\n"), edges.join(",\n"))
+}
+
 fn main() {
   let args: Vec<String> = env::args().collect();
   if args.len() < 2 {
@@ -107,7 +335,79 @@ fn main() {
     .expect("Error reading the file");
 
   let (numbers, symbols) = parse_engine(&input);
-  let adyacent_numbers = calculate_adyacent(&numbers, &symbols);
+
+  // "--export <dot|json> [--gears|--unattached]" prints the adyacency graph
+  // and "--neighbourhood <orthogonal|8|chebyshev:N|manhattan:N>" selects the
+  // cells that touch a symbol. The gears can be changed with "--symbols <chars>"
+  // and "--count <n|n+|min..max>" like in the part 2
+  let mut export: Option<String> = None;
+  let mut filter = GraphFilter::All;
+  let mut rule = GearRule { symbols: vec!['*'], count: NumberCount::Exactly(2) };
+  let mut neighbourhood = Neighbourhood::EightConnected;
+  let mut i = 2usize;
+  while i < args.len() {
+    match args[i].as_str() {
+      "--gears" => filter = GraphFilter::Gears,
+      "--unattached" => filter = GraphFilter::Unattached,
+      "--export" => {
+        match args.get(i + 1).map(|format| format.as_str()) {
+          Some("dot") | Some("json") => export = Some(args[i + 1].clone()),
+          _ => {
+            println!("Expected the export format, dot or json");
+            return;
+          }
+        }
+        i += 1;
+      },
+      "--symbols" => {
+        match args.get(i + 1) {
+          Some(value) => rule.symbols = value.chars().collect(),
+          None => {
+            println!("Expected the gear symbols after --symbols");
+            return;
+          }
+        }
+        i += 1;
+      },
+      "--count" => {
+        match args.get(i + 1).and_then(|value| parse_count(value)) {
+          Some(count) => rule.count = count,
+          None => {
+            println!("Expected a count of numbers: n, n+ or min..max");
+            return;
+          }
+        }
+        i += 1;
+      },
+      "--neighbourhood" => {
+        match args.get(i + 1).and_then(|value| parse_neighbourhood(value)) {
+          Some(new_neighbourhood) => neighbourhood = new_neighbourhood,
+          None => {
+            println!("Expected a neighbourhood: orthogonal, 8, chebyshev:N or manhattan:N");
+            return;
+          }
+        }
+        i += 1;
+      },
+      option => {
+        println!("Unknown option '{}'", option);
+        return;
+      }
+    }
+    i += 1;
+  }
+
+  if let Some(format) = export {
+    let graph = build_graph(&numbers, &symbols, filter, &rule, neighbourhood);
+    if format == "dot" {
+      print!("{}", graph_to_dot(&graph, &numbers, &symbols));
+    } else {
+      print!("{}", graph_to_json(&graph, &numbers, &symbols));
+    }
+    return;
+  }
+
+  let adyacent_numbers = calculate_adyacent(&numbers, &symbols, neighbourhood);
   let mut adyacent_sum = 0u64;
   for number in adyacent_numbers {
     adyacent_sum += number.number;
@@ -1,9 +1,10 @@
-use std::collections::LinkedList;
 use std::env;
 use std::fs;
 use std::u64;
 use std::usize;
 
+// The columns (begin, end and pos) are counted in chars, not in bytes, so
+// schematics with Unicode symbols keep the same coordinates in every row
 #[derive(Clone)]
 struct EngineNumber {
   row: usize,
@@ -18,113 +19,349 @@ struct EngineSymbol {
   symbol: char
 }
 
+// How many adyacent numbers a symbol needs to be a gear
+#[derive(Debug, Clone, Copy)]
+enum NumberCount {
+  Exactly(usize),
+  AtLeast(usize),
+  Between(usize, usize)
+}
+
+// How the adyacent numbers of a gear are combined into its ratio
+#[derive(Debug, Clone, Copy)]
+enum Aggregate {
+  Product,
+  Sum,
+  Max
+}
+
+#[derive(Debug, Clone)]
+struct GearRule {
+  symbols: Vec<char>,
+  count: NumberCount,
+  aggregate: Aggregate
+}
+
+// Cells around a symbol where a number touches it, the radius versions use
+// the Chebyshev (square) or Manhattan (diamond) distance
+#[derive(Debug, Clone, Copy)]
+enum Neighbourhood {
+  Orthogonal,
+  EightConnected,
+  Chebyshev(usize),
+  Manhattan(usize)
+}
+
+// Symbol that follows the gear rule, with its ratio
+struct Gear {
+  symbol: usize,
+  ratio: u64,
+  operation: String
+}
+
+// Part of the schematic shown when rendering
+#[derive(Debug, Clone, Copy)]
+struct Viewport {
+  row: usize,
+  col: usize,
+  height: usize,
+  width: usize
+}
+
+const PART_COLOUR: &str = "\x1b[32m";
+const UNATTACHED_COLOUR: &str = "\x1b[31m";
+const GEAR_COLOUR: &str = "\x1b[1;30;43m";
+const RESET_COLOUR: &str = "\x1b[0m";
+
+// Grid of cells with the index of the number that fills each cell
+struct NumberIndex {
+  rows: Vec<Vec<Option<usize>>>
+}
+
 fn is_digit(letter: char) -> bool {
   letter >= '0' && letter <= '9'
 }
 
+// Parse a number that ends just before the column next (the digits are
+// ASCII, so the length in chars is the same as in bytes)
+fn parse_number(row: usize, next: usize, number_str: &String) -> EngineNumber {
+  let number: u64 = number_str.parse().unwrap();
+  let begin = next - number_str.len();
+  let end = next - 1;
+
+  EngineNumber { row, begin, end, number }
+}
+
 // Parse the input to two list, one of engine numbers and other of engine symbols
-fn parse_engine(input: &String) -> (LinkedList<EngineNumber>, LinkedList<EngineSymbol>) {
-  let mut numbers: LinkedList<EngineNumber> = LinkedList::new();
-  let mut symbols: LinkedList<EngineSymbol> = LinkedList::new();
+fn parse_engine(input: &String) -> (Vec<EngineNumber>, Vec<EngineSymbol>) {
+  let mut numbers: Vec<EngineNumber> = Vec::new();
+  let mut symbols: Vec<EngineSymbol> = Vec::new();
 
   for (row, line) in input.lines().enumerate() {
     let mut number_str = String::new();
+    let mut width = 0usize;
     for (i, cell) in line.chars().enumerate() {
+      width = i + 1;
       if is_digit(cell) {
         number_str.push(cell);
         continue;
       }
 
       if !number_str.is_empty() {
-        let number: u64 = number_str.parse().unwrap();
-        let begin = i - number_str.len();
-        let end = i - 1;
+        numbers.push(parse_number(row, i, &number_str));
         number_str.clear();
-        numbers.push_back(EngineNumber { row, begin, end, number });
       }
      
       if cell == '.' {
         continue;
       }
 
-      symbols.push_back(EngineSymbol { row, pos: i, symbol: cell });
+      symbols.push(EngineSymbol { row, pos: i, symbol: cell });
     }
 
     if !number_str.is_empty() {
-      let number: u64 = number_str.parse().unwrap();
-      let begin = line.len() - number_str.len();
-      let end = line.len() - 1;
-      number_str.clear();
-      numbers.push_back(EngineNumber { row, begin, end, number });
+      numbers.push(parse_number(row, width, &number_str));
     }
   }
 
   (numbers, symbols)
 }
 
-// Checks if a number is adyacent to a symbol
-fn is_adyacent(number: &EngineNumber, symbol: &EngineSymbol) -> bool {
-  let begin = if number.begin == 0 {
-      number.begin
-    } else {
-      number.begin - 1
-    };
+// Build the cell index of the numbers
+fn build_index(numbers: &Vec<EngineNumber>) -> NumberIndex {
+  let mut rows: Vec<Vec<Option<usize>>> = Vec::new();
+  for (id, number) in numbers.iter().enumerate() {
+    if rows.len() <= number.row {
+      rows.resize(number.row + 1, Vec::new());
+    }
+
+    let cells = &mut rows[number.row];
+    if cells.len() <= number.end {
+      cells.resize(number.end + 1, None);
+    }
+
+    for cell in cells[number.begin..=number.end].iter_mut() {
+      *cell = Some(id);
+    }
+  }
 
-  if number.row == symbol.row {
-    (number.end + 1) == symbol.pos || begin == symbol.pos
-  } else if number.row.abs_diff(symbol.row) == 1 {
-    symbol.pos <= (number.end + 1) && symbol.pos >= begin
-  } else {
-    false
-  }
-}
-
-// Filters the numbers that are adyacent to one or more symbols in the list
-// fn calculate_adyacent(numbers: &LinkedList<EngineNumber>, symbols: &LinkedList<EngineSymbol>) -> LinkedList<EngineNumber> {
-//   let mut result: LinkedList<EngineNumber> = LinkedList::new();
-//   for number in numbers {
-//     for symbol in symbols {
-//       if is_adyacent(number, symbol) {
-//         println!("{} adyacent to {}", number.number, symbol.symbol);
-//         result.push_back(number.clone());
-//         break;
-//       }
-//     }
-//   }
-//
-//   result
-// }
-
-// Calculate the total gear ratio of the engine
-fn calculate_total_gear_ratio(numbers: &LinkedList<EngineNumber>, symbols: &LinkedList<EngineSymbol>) -> u64 {
+  NumberIndex { rows }
+}
+
+// Parse a neighbourhood like "orthogonal", "8", "chebyshev:2" or "manhattan:3"
+fn parse_neighbourhood(neighbourhood: &str) -> Option<Neighbourhood> {
+  match neighbourhood.split_once(':') {
+    Some(("chebyshev", radius)) => Some(Neighbourhood::Chebyshev(radius.parse().ok()?)),
+    Some(("manhattan", radius)) => Some(Neighbourhood::Manhattan(radius.parse().ok()?)),
+    Some(_) => None,
+    None => match neighbourhood {
+      "orthogonal" => Some(Neighbourhood::Orthogonal),
+      "8" => Some(Neighbourhood::EightConnected),
+      _ => None
+    }
+  }
+}
+
+// Indexes of the numbers adyacent to a symbol in ascending order, only the
+// cells of the neighbourhood inside the grid are visited (the radius can be
+// larger than the grid)
+fn adyacent_numbers(index: &NumberIndex, symbol: &EngineSymbol, neighbourhood: Neighbourhood) -> Vec<usize> {
+  let (radius, manhattan) = match neighbourhood {
+    Neighbourhood::Orthogonal => (1, true),
+    Neighbourhood::EightConnected => (1, false),
+    Neighbourhood::Chebyshev(radius) => (radius, false),
+    Neighbourhood::Manhattan(radius) => (radius, true)
+  };
+
+  let mut result: Vec<usize> = Vec::new();
+  let first_row = symbol.row.saturating_sub(radius);
+  let end_row = symbol.row.saturating_add(radius).saturating_add(1).min(index.rows.len());
+  for row in first_row..end_row {
+    let reach = if manhattan { radius - row.abs_diff(symbol.row) } else { radius };
+    let cells = &index.rows[row];
+    let first_pos = symbol.pos.saturating_sub(reach);
+    let end_pos = symbol.pos.saturating_add(reach).saturating_add(1).min(cells.len());
+    for id in cells[first_pos.min(end_pos)..end_pos].iter().flatten() {
+      result.push(*id);
+    }
+  }
+
+  result.sort();
+  result.dedup();
+  result
+}
+
+// Rule of the part 2: '*' with exactly two numbers, multiplied
+fn default_gear_rule() -> GearRule {
+  GearRule {
+    symbols: vec!['*'],
+    count: NumberCount::Exactly(2),
+    aggregate: Aggregate::Product
+  }
+}
+
+// Parse a count like "2" (exactly), "2+" (at least) or "2..4" (both included)
+fn parse_count(count: &str) -> Option<NumberCount> {
+  if let Some(min) = count.strip_suffix('+') {
+    return Some(NumberCount::AtLeast(min.parse().ok()?));
+  }
+
+  if let Some((min, max)) = count.split_once("..") {
+    let min: usize = min.parse().ok()?;
+    let max: usize = max.parse().ok()?;
+    if min > max {
+      return None;
+    }
+    return Some(NumberCount::Between(min, max));
+  }
+
+  Some(NumberCount::Exactly(count.parse().ok()?))
+}
+
+fn parse_aggregate(aggregate: &str) -> Option<Aggregate> {
+  match aggregate {
+    "product" => Some(Aggregate::Product),
+    "sum" => Some(Aggregate::Sum),
+    "max" => Some(Aggregate::Max),
+    _ => None
+  }
+}
+
+fn count_matches(count: NumberCount, n: usize) -> bool {
+  match count {
+    NumberCount::Exactly(exact) => n == exact,
+    NumberCount::AtLeast(min) => n >= min,
+    NumberCount::Between(min, max) => n >= min && n <= max
+  }
+}
+
+// Calculates the ratio of a gear and the text of the operation, a ratio past
+// the u64 limit is an error
+fn aggregate_numbers(aggregate: Aggregate, values: &Vec<u64>) -> Result<(u64, String), String> {
+  let values_str: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+  let (ratio, operation) = match aggregate {
+    Aggregate::Product => (
+      values.iter().try_fold(1u64, |product, value| product.checked_mul(*value)),
+      values_str.join(" x ")
+    ),
+    Aggregate::Sum => (
+      values.iter().try_fold(0u64, |sum, value| sum.checked_add(*value)),
+      values_str.join(" + ")
+    ),
+    Aggregate::Max => (
+      Some(values.iter().cloned().max().unwrap_or(0)),
+      format!("max({})", values_str.join(", "))
+    )
+  };
+
+  match ratio {
+    Some(ratio) => Ok((ratio, operation)),
+    None => Err(format!("Gear ratio {} past the integer limit {}", operation, u64::MAX))
+  }
+}
+
+// Find the symbols that follow the gear rule
+fn find_gears(numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>, rule: &GearRule, neighbourhood: Neighbourhood) -> Result<Vec<Gear>, String> {
+  let index = build_index(numbers);
+  let mut gears: Vec<Gear> = Vec::new();
+  for (symbol_id, symbol) in symbols.iter().enumerate() {
+    if !rule.symbols.contains(&symbol.symbol) {
+      continue;
+    }
+
+    let adyacents = adyacent_numbers(&index, symbol, neighbourhood);
+    if count_matches(rule.count, adyacents.len()) {
+      let values: Vec<u64> = adyacents.iter().map(|id| numbers[*id].number).collect();
+      let (ratio, operation) = aggregate_numbers(rule.aggregate, &values)?;
+      gears.push(Gear { symbol: symbol_id, ratio, operation });
+    }
+  }
+
+  Ok(gears)
+}
+
+// Calculate the total gear ratio of the engine with a gear rule
+fn calculate_total_gear_ratio(numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>, rule: &GearRule, neighbourhood: Neighbourhood) -> Result<u64, String> {
   let mut global_gear_ratio = 0u64;
+  for gear in find_gears(numbers, symbols, rule, neighbourhood)? {
+    println!("{} = {}", gear.operation, gear.ratio);
+    global_gear_ratio = global_gear_ratio.checked_add(gear.ratio)
+      .ok_or(format!("Global gear ratio {} + {} past the integer limit {}", global_gear_ratio, gear.ratio, u64::MAX))?;
+  }
+
+  Ok(global_gear_ratio)
+}
+
+// Marks the numbers that are adyacent to one or more symbols
+fn attached_numbers(numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>, neighbourhood: Neighbourhood) -> Vec<bool> {
+  let index = build_index(numbers);
+  let mut attached: Vec<bool> = vec![false; numbers.len()];
   for symbol in symbols {
-    if symbol.symbol != '*' {
-      continue;
+    for id in adyacent_numbers(&index, symbol, neighbourhood) {
+      attached[id] = true;
     }
+  }
 
-    let mut adyacents: Vec<EngineNumber> = Vec::new();
-    adyacents.reserve(3);
-    for number in numbers {
-      if !is_adyacent(number, symbol) {
-        continue;
-      }
+  attached
+}
 
-      if adyacents.len() > 2 {
-        break;
-      }
+// Parse a viewport like "row,col,height,width"
+fn parse_viewport(viewport: &str) -> Option<Viewport> {
+  let values: Vec<usize> = viewport.split(',')
+    .map(|value| value.trim().parse().ok())
+    .collect::<Option<Vec<usize>>>()?;
+  if values.len() != 4 {
+    return None;
+  }
 
-      adyacents.push(number.clone());
+  Some(Viewport { row: values[0], col: values[1], height: values[2], width: values[3] })
+}
+
+// Render the schematic with colours: part numbers in green, unattached numbers
+// in red and gears highlighted, with the ratios of the gears of every row at
+// its right side
+fn render_schematic(input: &String, numbers: &Vec<EngineNumber>, symbols: &Vec<EngineSymbol>, gears: &Vec<Gear>, attached: &Vec<bool>, viewport: Viewport) -> String {
+  let grid: Vec<Vec<char>> = input.lines().map(|line| line.chars().collect()).collect();
+  let mut colours: Vec<Vec<&str>> = grid.iter().map(|line| vec![""; line.len()]).collect();
+
+  for (number, is_attached) in numbers.iter().zip(attached.iter()) {
+    let colour = if *is_attached { PART_COLOUR } else { UNATTACHED_COLOUR };
+    for cell in colours[number.row][number.begin..=number.end].iter_mut() {
+      *cell = colour;
     }
+  }
 
-    if adyacents.len() == 2 {
-      let gear_ratio = adyacents[0].number * adyacents[1].number;
-      println!("{} x {} = {}", adyacents[0].number, adyacents[1].number, gear_ratio);
-      global_gear_ratio += gear_ratio;
+  let mut legends: Vec<Vec<String>> = vec![Vec::new(); grid.len()];
+  for gear in gears {
+    let symbol = &symbols[gear.symbol];
+    colours[symbol.row][symbol.pos] = GEAR_COLOUR;
+    if symbol.pos >= viewport.col && symbol.pos - viewport.col < viewport.width {
+      legends[symbol.row].push(format!("{}@{}: {} = {}", symbol.symbol, symbol.pos, gear.operation, gear.ratio));
     }
   }
 
-  global_gear_ratio
+  let mut output = String::new();
+  let last_row = grid.len().min(viewport.row.saturating_add(viewport.height));
+  for row in viewport.row.min(last_row)..last_row {
+    let mut current = "";
+    for (cell, colour) in grid[row].iter().zip(colours[row].iter()).skip(viewport.col).take(viewport.width) {
+      if *colour != current {
+        output += RESET_COLOUR;
+        output += colour;
+        current = colour;
+      }
+      output.push(*cell);
+    }
+    output += RESET_COLOUR;
+
+    if !legends[row].is_empty() {
+      output += "  ";
+      output += &legends[row].join(", ");
+    }
+    output += "\n";
+  }
+
+  output
 }
 
 fn main() {
@@ -138,7 +375,84 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
 
+  // The gear rule can be changed with "--symbols <chars>",
+  // "--count <n|n+|min..max>" and "--aggregate <product|sum|max>", and the
+  // cells that touch a symbol with "--neighbourhood <orthogonal|8|chebyshev:N|manhattan:N>"
+  let mut rule = default_gear_rule();
+  let mut neighbourhood = Neighbourhood::EightConnected;
+  // "--render [--crop row,col,height,width]" prints the coloured schematic
+  let mut render = false;
+  let mut viewport = Viewport { row: 0, col: 0, height: usize::MAX, width: usize::MAX };
+  let mut i = 2usize;
+  while i < args.len() {
+    if args[i] == "--render" {
+      render = true;
+      i += 1;
+      continue;
+    }
+
+    let value = match args.get(i + 1) {
+      Some(value) => value.as_str(),
+      None => {
+        println!("Missing the value of {}", args[i]);
+        return;
+      }
+    };
+
+    match args[i].as_str() {
+      "--symbols" => rule.symbols = value.chars().collect(),
+      "--count" => match parse_count(value) {
+        Some(count) => rule.count = count,
+        None => {
+          println!("Invalid count '{}', expected n, n+ or min..max", value);
+          return;
+        }
+      },
+      "--aggregate" => match parse_aggregate(value) {
+        Some(aggregate) => rule.aggregate = aggregate,
+        None => {
+          println!("Invalid aggregate '{}', expected product, sum or max", value);
+          return;
+        }
+      },
+      "--neighbourhood" => match parse_neighbourhood(value) {
+        Some(new_neighbourhood) => neighbourhood = new_neighbourhood,
+        None => {
+          println!("Invalid neighbourhood '{}', expected orthogonal, 8, chebyshev:N or manhattan:N", value);
+          return;
+        }
+      },
+      "--crop" => match parse_viewport(value) {
+        Some(new_viewport) => viewport = new_viewport,
+        None => {
+          println!("Invalid crop '{}', expected row,col,height,width", value);
+          return;
+        }
+      },
+      option => {
+        println!("Unknown option '{}'", option);
+        return;
+      }
+    }
+    i += 2;
+  }
+
   let (numbers, symbols) = parse_engine(&input);
-  let global_gear_ratio = calculate_total_gear_ratio(&numbers, &symbols);
-  println!("Global gear ratio: {}", global_gear_ratio);
+  if render {
+    let gears = match find_gears(&numbers, &symbols, &rule, neighbourhood) {
+      Ok(gears) => gears,
+      Err(error) => {
+        println!("Error: {}", error);
+        return;
+      }
+    };
+    let attached = attached_numbers(&numbers, &symbols, neighbourhood);
+    print!("{}", render_schematic(&input, &numbers, &symbols, &gears, &attached, viewport));
+    return;
+  }
+
+  match calculate_total_gear_ratio(&numbers, &symbols, &rule, neighbourhood) {
+    Ok(global_gear_ratio) => println!("Global gear ratio: {}", global_gear_ratio),
+    Err(error) => println!("Error: {}", error)
+  }
 }
@@ -1,13 +1,71 @@
 use std::collections::HashSet;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::{u32, u64};
 
+const BITSET_WORDS: usize = 2;
+const BITSET_LIMIT: u32 = 64 * BITSET_WORDS as u32;
+
+// Set of card numbers, the numbers below BITSET_LIMIT are stored in a bitset
+// and the rest in a hash set
+#[derive(Clone, Default)]
+struct NumberSet {
+  bits: [u64; BITSET_WORDS],
+  large: HashSet<u32>
+}
+
 #[derive(Debug)]
 struct Card {
   id: usize,
-  winners: HashSet<u32>,
-  numbers: HashSet<u32>
+  winners: NumberSet,
+  numbers: NumberSet
+}
+
+fn insert_number(set: &mut NumberSet, number: u32) {
+  if number < BITSET_LIMIT {
+    set.bits[(number / 64) as usize] |= 1u64 << (number % 64);
+  } else {
+    set.large.insert(number);
+  }
+}
+
+// Counts the numbers in both sets
+fn count_common(a: &NumberSet, b: &NumberSet) -> usize {
+  let small: u32 = a.bits.iter().zip(b.bits.iter())
+    .map(|(a_bits, b_bits)| (a_bits & b_bits).count_ones())
+    .sum();
+
+  small as usize + a.large.intersection(&b.large).count()
+}
+
+impl fmt::Debug for NumberSet {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut list = f.debug_set();
+    for (word, bits) in self.bits.iter().enumerate() {
+      let mut bits = *bits;
+      while bits != 0 {
+        list.entry(&(word as u32 * 64 + bits.trailing_zeros()));
+        bits &= bits - 1;
+      }
+    }
+
+    if !self.large.is_empty() {
+      let mut large: Vec<&u32> = self.large.iter().collect();
+      large.sort();
+      list.entries(large);
+    }
+    list.finish()
+  }
+}
+
+// Points of a card by its number of matches: doubling is 1, 2, 4, 8...,
+// linear 1, 2, 3, 4... and fibonacci 1, 1, 2, 3, 5...
+#[derive(Debug, Clone, Copy)]
+enum PointsRule {
+  Doubling,
+  Linear,
+  Fibonacci
 }
 
 // Parse the card input into a structure data
@@ -16,8 +74,8 @@ fn parse_card(card: &String) -> Card {
   let mut reading_winners = true;
   let mut result = Card {
     id: 0,
-    winners: HashSet::new(),
-    numbers: HashSet::new()
+    winners: NumberSet::default(),
+    numbers: NumberSet::default()
   };
 
   for token in card.split([' ', ':']) {
@@ -43,24 +101,49 @@ fn parse_card(card: &String) -> Card {
 
     let number: u32 = token.parse().unwrap();
     if reading_winners {
-      result.winners.insert(number);
+      insert_number(&mut result.winners, number);
     } else {
-      result.numbers.insert(number);
+      insert_number(&mut result.numbers, number);
     }
   }
 
   result
 }
 
-// Calculates the points of a card
-fn calculate_card_points(card: &Card) -> u64 {
-  let number_winners = card.numbers.intersection(&card.winners).count();
+fn parse_points_rule(rule: &str) -> Option<PointsRule> {
+  match rule {
+    "doubling" => Some(PointsRule::Doubling),
+    "linear" => Some(PointsRule::Linear),
+    "fibonacci" => Some(PointsRule::Fibonacci),
+    _ => None
+  }
+}
+
+// Calculates the points of a card, more points than the u64 limit (above 64
+// matches doubling or 93 fibonacci) are an error
+fn calculate_card_points(card: &Card, rule: PointsRule) -> Result<u64, String> {
+  let number_winners = count_common(&card.numbers, &card.winners);
   
   if number_winners == 0 {
-    0
-  } else {
-    2u64.pow(number_winners as u32 - 1)
+    return Ok(0);
   }
+
+  let points = match rule {
+    PointsRule::Doubling => 2u64.checked_pow(number_winners as u32 - 1),
+    PointsRule::Linear => Some(number_winners as u64),
+    PointsRule::Fibonacci => {
+      let (mut previous, mut current) = (0u64, Some(1u64));
+      for _ in 1..number_winners {
+        (previous, current) = match current {
+          Some(value) => (value, previous.checked_add(value)),
+          None => break
+        };
+      }
+      current
+    }
+  };
+
+  points.ok_or(format!("Points of card {} with {} matches past the integer limit", card.id, number_winners))
 }
 
 fn main() {
@@ -74,14 +157,67 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
 
+  // The points rule can be given with "--points <doubling|linear|fibonacci>"
+  let mut rule = PointsRule::Doubling;
+  if args.len() > 2 && args[2] == "--points" {
+    rule = match args.get(3).and_then(|rule| parse_points_rule(rule)) {
+      Some(rule) => rule,
+      None => {
+        println!("Expected a points rule: doubling, linear or fibonacci");
+        return;
+      }
+    };
+  }
+
   let mut total_points = 0u64;
 
   for line in input.lines() {
     let card = parse_card(&line.to_string());
-    let points = calculate_card_points(&card);
-    total_points += points;
+    let points = match calculate_card_points(&card, rule) {
+      Ok(points) => points,
+      Err(error) => {
+        println!("Error: {}", error);
+        return;
+      }
+    };
+    total_points = match total_points.checked_add(points) {
+      Some(total_points) => total_points,
+      None => {
+        println!("Error: Total points past the integer limit");
+        return;
+      }
+    };
     println!("{:?}\npoints: {}", card, points);
   }
 
   println!("Total points: {}", total_points); 
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Card with the numbers 1..=n winning
+  fn card_with_matches(n: u32) -> Card {
+    let numbers: Vec<String> = (1..=n).map(|number| number.to_string()).collect();
+    parse_card(&format!("Card 1: {} | {} 500", numbers.join(" "), numbers.join(" ")))
+  }
+
+  #[test]
+  fn points_rules() {
+    let points = |rule: PointsRule| -> Vec<u64> {
+      (0..7).map(|n| calculate_card_points(&card_with_matches(n), rule).unwrap()).collect()
+    };
+    assert_eq!(points(PointsRule::Doubling), vec![0, 1, 2, 4, 8, 16, 32]);
+    assert_eq!(points(PointsRule::Linear), vec![0, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(points(PointsRule::Fibonacci), vec![0, 1, 1, 2, 3, 5, 8]);
+  }
+
+  #[test]
+  fn points_past_the_integer_limit() {
+    assert_eq!(calculate_card_points(&card_with_matches(64), PointsRule::Doubling), Ok(1 << 63));
+    assert!(calculate_card_points(&card_with_matches(65), PointsRule::Doubling).is_err());
+    assert_eq!(calculate_card_points(&card_with_matches(93), PointsRule::Fibonacci), Ok(12200160415121876738));
+    assert!(calculate_card_points(&card_with_matches(94), PointsRule::Fibonacci).is_err());
+  }
+}
@@ -1,15 +1,106 @@
+use std::collections::BTreeMap;
 use std::collections::HashSet;
 use std::collections::LinkedList;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::usize;
 use std::{u32, u64};
 
+const BITSET_WORDS: usize = 2;
+const BITSET_LIMIT: u32 = 64 * BITSET_WORDS as u32;
+
+// Set of card numbers, the numbers below BITSET_LIMIT are stored in a bitset
+// and the rest in a hash set
+#[derive(Clone, Default)]
+struct NumberSet {
+  bits: [u64; BITSET_WORDS],
+  large: HashSet<u32>
+}
+
 #[derive(Debug)]
 struct Card {
   id: usize,
-  winners: HashSet<u32>,
-  numbers: HashSet<u32>
+  winners: NumberSet,
+  numbers: NumberSet
+}
+
+fn insert_number(set: &mut NumberSet, number: u32) {
+  if number < BITSET_LIMIT {
+    set.bits[(number / 64) as usize] |= 1u64 << (number % 64);
+  } else {
+    set.large.insert(number);
+  }
+}
+
+// Numbers of a set in ascending order
+fn set_numbers(set: &NumberSet) -> Vec<u32> {
+  let mut numbers: Vec<u32> = Vec::new();
+  for (word, bits) in set.bits.iter().enumerate() {
+    let mut bits = *bits;
+    while bits != 0 {
+      numbers.push(word as u32 * 64 + bits.trailing_zeros());
+      bits &= bits - 1;
+    }
+  }
+
+  let mut large: Vec<u32> = set.large.iter().cloned().collect();
+  large.sort();
+  numbers.extend(large);
+  numbers
+}
+
+impl fmt::Debug for NumberSet {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut list = f.debug_set();
+    for (word, bits) in self.bits.iter().enumerate() {
+      let mut bits = *bits;
+      while bits != 0 {
+        list.entry(&(word as u32 * 64 + bits.trailing_zeros()));
+        bits &= bits - 1;
+      }
+    }
+
+    if !self.large.is_empty() {
+      let mut large: Vec<&u32> = self.large.iter().collect();
+      large.sort();
+      list.entries(large);
+    }
+    list.finish()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CopyDirection {
+  Next,
+  Previous
+}
+
+// Rule of the copies cascade, every copy of a card with N matches gives
+// multiplier copies to each of the N next (or previous) cards
+#[derive(Debug, Clone, Copy)]
+struct CopyRule {
+  direction: CopyDirection,
+  multiplier: u64
+}
+
+const DEFAULT_COPY_RULE: CopyRule = CopyRule {
+  direction: CopyDirection::Next,
+  multiplier: 1
+};
+
+// Trace of a card in the copies cascade, clamped counts the wins out of the
+// cards table and missing has the won ids inside the table without a card
+#[derive(Debug, Clone)]
+struct CardTrace<'a> {
+  id: usize,
+  card: &'a Card,
+  wins: usize,
+  gives_to: Vec<usize>,
+  clamped: usize,
+  missing: Vec<usize>,
+  copies: u64,
+  received_from: Vec<(usize, u64)>
 }
 
 // Parse the card input into a structure data
@@ -18,8 +109,8 @@ fn parse_card(card: &String) -> Card {
   let mut reading_winners = true;
   let mut result = Card {
     id: 0,
-    winners: HashSet::new(),
-    numbers: HashSet::new()
+    winners: NumberSet::default(),
+    numbers: NumberSet::default()
   };
 
   for token in card.split([' ', ':']) {
@@ -45,33 +136,178 @@ fn parse_card(card: &String) -> Card {
 
     let number: u32 = token.parse().unwrap();
     if reading_winners {
-      result.winners.insert(number);
+      insert_number(&mut result.winners, number);
     } else {
-      result.numbers.insert(number);
+      insert_number(&mut result.numbers, number);
     }
   }
 
   result
 }
 
-// Calculates the number of winners
-fn card_winners(card: &Card) -> usize {
-  card.numbers.intersection(&card.winners).count()
+// Counts the numbers in both sets
+fn count_common(a: &NumberSet, b: &NumberSet) -> usize {
+  let small: u32 = a.bits.iter().zip(b.bits.iter())
+    .map(|(a_bits, b_bits)| (a_bits & b_bits).count_ones())
+    .sum();
+
+  small as usize + a.large.intersection(&b.large).count()
 }
 
-// Calculates the total cards winned
-fn calculate_winned_cards(cards: &LinkedList<Card>) -> u64 {
-  let mut copies: Vec<u64> = Vec::new();
-  copies.resize(cards.len(), 1);
+// Calculates the winner numbers of a card, sorted (only for the reports, the
+// cascade counts them with count_common)
+fn card_matches(card: &Card) -> Vec<u32> {
+  let mut common = NumberSet::default();
+  for (word, bits) in common.bits.iter_mut().enumerate() {
+    *bits = card.numbers.bits[word] & card.winners.bits[word];
+  }
+  common.large = card.numbers.large.intersection(&card.winners.large).cloned().collect();
+
+  set_numbers(&common)
+}
 
+// Runs the copies cascade, the cards are processed in id order (reversed if
+// the copies go to the previous cards, the ids can have gaps) and the wins
+// out of the cards table are clamped. The wins of ids in a gap are lost and
+// more copies than the u64 limit are an error
+fn cascade_cards<'a>(cards: &'a LinkedList<Card>, rule: &CopyRule) -> Result<Vec<CardTrace<'a>>, String> {
+  let mut traces: BTreeMap<usize, CardTrace> = BTreeMap::new();
   for card in cards {
-    let card_wins = card_winners(card);
-    for id in card.id..card.id+card_wins {
-      copies[id] += copies[card.id - 1];
+    let trace = CardTrace {
+      id: card.id,
+      card,
+      wins: count_common(&card.numbers, &card.winners),
+      gives_to: Vec::new(),
+      clamped: 0,
+      missing: Vec::new(),
+      copies: 1,
+      received_from: Vec::new()
+    };
+
+    if traces.insert(card.id, trace).is_some() {
+      return Err(format!("Duplicated card {}", card.id));
+    }
+  }
+
+  let (first_id, last_id) = match (traces.keys().next(), traces.keys().last()) {
+    (Some(first_id), Some(last_id)) => (*first_id, *last_id),
+    _ => return Ok(Vec::new())
+  };
+
+  let mut ids: Vec<usize> = traces.keys().cloned().collect();
+  if rule.direction == CopyDirection::Previous {
+    ids.reverse();
+  }
+
+  for card_id in ids {
+    let card_wins = traces[&card_id].wins;
+    if card_wins == 0 {
+      continue;
     }
+
+    let card_copies = traces[&card_id].copies.checked_mul(rule.multiplier)
+      .ok_or(format!("Copies of card {} past the integer limit ({} x {})", card_id, traces[&card_id].copies, rule.multiplier))?;
+    // Range of winned ids [begin, end)
+    let (begin, end, clamped) = match rule.direction {
+      CopyDirection::Next => {
+        let last_winned = card_id + card_wins;
+        (card_id + 1, last_winned + 1, last_winned.saturating_sub(last_id))
+      },
+      CopyDirection::Previous => {
+        let first_winned = card_id as i64 - card_wins as i64;
+        let clamped = (first_id as i64 - first_winned).clamp(0, card_wins as i64);
+        (first_winned.max(0) as usize, card_id, clamped as usize)
+      }
+    };
+
+    let mut gives_to: Vec<usize> = Vec::new();
+    for (id, trace) in traces.range_mut(begin..end) {
+      trace.copies = trace.copies.checked_add(card_copies)
+        .ok_or(format!("Copies of card {} past the integer limit ({} + {})", id, trace.copies, card_copies))?;
+      trace.received_from.push((card_id, card_copies));
+      gives_to.push(*id);
+    }
+
+    let missing: Vec<usize> = (begin.max(first_id)..end.min(last_id + 1))
+      .filter(|id| !traces.contains_key(id))
+      .collect();
+
+    let trace = traces.get_mut(&card_id).unwrap();
+    trace.gives_to = gives_to;
+    trace.clamped = clamped;
+    trace.missing = missing;
   }
 
-  copies.iter().sum()
+  Ok(traces.into_values().collect())
+}
+
+// Calculates the total cards winned
+fn calculate_winned_cards(cards: &LinkedList<Card>, rule: &CopyRule) -> Result<u64, String> {
+  let traces = cascade_cards(cards, rule)?;
+  for trace in traces.iter() {
+    if !trace.missing.is_empty() {
+      println!("Card {} wins the missing cards {}", trace.id, join_list(&trace.missing, " "));
+    }
+
+    if trace.clamped == 0 {
+      continue;
+    }
+
+    if rule.direction == CopyDirection::Next {
+      let last_id = traces.last().unwrap().id;
+      println!("Card {} wins {} cards past the last card {}", trace.id, trace.clamped, last_id);
+    } else {
+      let first_id = traces.first().unwrap().id;
+      println!("Card {} wins {} cards before the first card {}", trace.id, trace.clamped, first_id);
+    }
+  }
+
+  traces.iter().try_fold(0u64, |total, trace| total.checked_add(trace.copies))
+    .ok_or("Total cards past the integer limit".to_string())
+}
+
+fn join_list<T: ToString>(list: &Vec<T>, separator: &str) -> String {
+  list.iter().map(|e| e.to_string()).collect::<Vec<String>>().join(separator)
+}
+
+// Table with the trace of every card
+fn trace_table(traces: &Vec<CardTrace>) -> String {
+  let mut table = String::from("Card | Matches | Gives to | Copies | Received from\n");
+  for trace in traces {
+    let matches = card_matches(trace.card);
+    let mut gives_to = join_list(&trace.gives_to, " ");
+    if !trace.missing.is_empty() {
+      gives_to = format!("{} (missing {})", gives_to, join_list(&trace.missing, " ")).trim_start().to_string();
+    }
+    if trace.clamped > 0 {
+      gives_to = format!("{} (+{} out of the table)", gives_to, trace.clamped).trim_start().to_string();
+    }
+
+    let received_from: Vec<String> = trace.received_from.iter()
+      .map(|(id, copies)| format!("{} x{}", id, copies))
+      .collect();
+
+    table += &format!("{} | {} ({}) | {} | {} | {}\n",
+      trace.id, trace.wins, join_list(&matches, " "),
+      gives_to, trace.copies, received_from.join(", "));
+  }
+
+  table
+}
+
+// JSON with the trace of every card
+fn trace_json(traces: &Vec<CardTrace>) -> String {
+  let cards: Vec<String> = traces.iter().map(|trace| {
+    let received_from: Vec<String> = trace.received_from.iter()
+      .map(|(id, copies)| format!("{{\"card\": {}, \"copies\": {}}}", id, copies))
+      .collect();
+
+    format!("  {{\"card\": {}, \"matches\": [{}], \"gives_to\": [{}], \"clamped\": {}, \"missing\": [{}], \"copies\": {}, \"received_from\": [{}]}}",
+      trace.id, join_list(&card_matches(trace.card), ", "), join_list(&trace.gives_to, ", "),
+      trace.clamped, join_list(&trace.missing, ", "), trace.copies, received_from.join(", "))
+  }).collect();
+
+  format!("[\n{}\n]\n", cards.join(",\n"))
 }
 
 fn main() {
@@ -92,6 +328,162 @@ fn main() {
     card_list.push_back(card);
   }
 
-  let total_cards = calculate_winned_cards(&card_list);
-  println!("Total cards: {}", total_cards);
+  // "--report <table|json>" prints the trace of the copies cascade, the rule
+  // can be changed with "--copies <next|previous>" and "--multiplier <n>"
+  let mut report: Option<String> = None;
+  let mut rule = DEFAULT_COPY_RULE;
+  let mut i = 2usize;
+  while i < args.len() {
+    let value = match args.get(i + 1) {
+      Some(value) => value.as_str(),
+      None => {
+        println!("Missing the value of {}", args[i]);
+        return;
+      }
+    };
+
+    match args[i].as_str() {
+      "--report" => match value {
+        "table" | "json" => report = Some(value.to_string()),
+        _ => {
+          println!("Invalid report format '{}', expected table or json", value);
+          return;
+        }
+      },
+      "--copies" => match value {
+        "next" => rule.direction = CopyDirection::Next,
+        "previous" => rule.direction = CopyDirection::Previous,
+        _ => {
+          println!("Invalid copies rule '{}', expected next or previous", value);
+          return;
+        }
+      },
+      "--multiplier" => match value.parse() {
+        Ok(multiplier) => rule.multiplier = multiplier,
+        Err(_) => {
+          println!("Invalid multiplier '{}'", value);
+          return;
+        }
+      },
+      option => {
+        println!("Unknown option '{}'", option);
+        return;
+      }
+    }
+    i += 2;
+  }
+
+  if let Some(format) = report {
+    let traces = match cascade_cards(&card_list, &rule) {
+      Ok(traces) => traces,
+      Err(error) => {
+        println!("Error: {}", error);
+        return;
+      }
+    };
+
+    if format == "table" {
+      print!("{}", trace_table(&traces));
+    } else {
+      print!("{}", trace_json(&traces));
+    }
+    return;
+  }
+
+  match calculate_winned_cards(&card_list, &rule) {
+    Ok(total_cards) => println!("Total cards: {}", total_cards),
+    Err(error) => println!("Error: {}", error)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cards(lines: &[&str]) -> LinkedList<Card> {
+    lines.iter().map(|line| parse_card(&line.to_string())).collect()
+  }
+
+  fn copies(traces: &Vec<CardTrace>) -> Vec<(usize, u64)> {
+    traces.iter().map(|trace| (trace.id, trace.copies)).collect()
+  }
+
+  #[test]
+  fn gaps_in_the_ids() {
+    let card_list = cards(&["Card 1: 1 2 | 1 2", "Card 2: 5 | 6", "Card 4: 3 | 4"]);
+    let traces = cascade_cards(&card_list, &DEFAULT_COPY_RULE).unwrap();
+    assert_eq!(copies(&traces), vec![(1, 1), (2, 2), (4, 1)]);
+    assert_eq!(traces[0].gives_to, vec![2]);
+    assert_eq!(traces[0].missing, vec![3]);
+    assert_eq!(traces[0].clamped, 0);
+  }
+
+  #[test]
+  fn wins_lost_in_a_gap_are_reported() {
+    let card_list = cards(&["Card 3: 1 | 1", "Card 5: 2 | 3"]);
+    let traces = cascade_cards(&card_list, &DEFAULT_COPY_RULE).unwrap();
+    assert_eq!(copies(&traces), vec![(3, 1), (5, 1)]);
+    assert_eq!(traces[0].missing, vec![4]);
+    assert!(trace_table(&traces).contains("(missing 4)"));
+  }
+
+  #[test]
+  fn out_of_order_lines() {
+    let ordered = ["Card 1: 1 2 | 1 2", "Card 2: 3 | 3", "Card 3: 4 | 5", "Card 4: 6 | 6"];
+    let shuffled = [ordered[2], ordered[0], ordered[3], ordered[1]];
+    let (ordered_list, shuffled_list) = (cards(&ordered), cards(&shuffled));
+    let expected = cascade_cards(&ordered_list, &DEFAULT_COPY_RULE).unwrap();
+    let traces = cascade_cards(&shuffled_list, &DEFAULT_COPY_RULE).unwrap();
+    assert_eq!(copies(&traces), copies(&expected));
+    assert_eq!(copies(&traces), vec![(1, 1), (2, 2), (3, 4), (4, 1)]);
+  }
+
+  #[test]
+  fn duplicated_cards() {
+    let card_list = cards(&["Card 1: 1 | 2", "Card 1: 3 | 3"]);
+    let result = cascade_cards(&card_list, &DEFAULT_COPY_RULE);
+    assert_eq!(result.unwrap_err(), "Duplicated card 1");
+  }
+
+  #[test]
+  fn wins_past_the_end() {
+    let card_list = cards(&["Card 1: 1 | 2", "Card 2: 1 2 3 | 1 2 3"]);
+    let traces = cascade_cards(&card_list, &DEFAULT_COPY_RULE).unwrap();
+    assert_eq!(copies(&traces), vec![(1, 1), (2, 1)]);
+    assert_eq!(traces[1].clamped, 3);
+    assert!(traces[1].missing.is_empty());
+
+    let rule = CopyRule { direction: CopyDirection::Previous, multiplier: 1 };
+    let card_list = cards(&["Card 1: 1 2 | 1 2", "Card 2: 1 | 1"]);
+    let traces = cascade_cards(&card_list, &rule).unwrap();
+    assert_eq!(copies(&traces), vec![(1, 2), (2, 1)]);
+    assert_eq!(traces[0].clamped, 2);
+    assert_eq!(traces[1].gives_to, vec![1]);
+  }
+
+  #[test]
+  fn copy_rules() {
+    let card_list = cards(&["Card 1: 1 2 | 1 2", "Card 2: 1 | 1", "Card 3: 1 | 2"]);
+    let rule = CopyRule { direction: CopyDirection::Next, multiplier: 3 };
+    let traces = cascade_cards(&card_list, &rule).unwrap();
+    assert_eq!(copies(&traces), vec![(1, 1), (2, 4), (3, 16)]);
+    assert_eq!(traces[2].received_from, vec![(1, 3), (2, 12)]);
+
+    let rule = CopyRule { direction: CopyDirection::Previous, multiplier: 2 };
+    let card_list = cards(&["Card 1: 1 | 2", "Card 2: 1 | 1", "Card 3: 1 2 | 1 2"]);
+    let traces = cascade_cards(&card_list, &rule).unwrap();
+    assert_eq!(copies(&traces), vec![(1, 9), (2, 3), (3, 1)]);
+    assert_eq!(calculate_winned_cards(&card_list, &rule), Ok(13));
+  }
+
+  #[test]
+  fn copies_past_the_integer_limit() {
+    let lines: Vec<String> = (1..=30).map(|id| format!("Card {}: 1 2 | 1 2", id)).collect();
+    let lines: Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+    let card_list = cards(&lines);
+    let rule = CopyRule { direction: CopyDirection::Next, multiplier: 10 };
+    assert!(cascade_cards(&card_list, &rule).unwrap_err().contains("past the integer limit"));
+    assert!(calculate_winned_cards(&card_list, &rule).is_err());
+    assert!(calculate_winned_cards(&card_list, &DEFAULT_COPY_RULE).is_ok());
+  }
 }
@@ -0,0 +1,988 @@
+// Almanac maps and the graph of categories shared by both parts
+
+use crate::interval_map::{Interval, IntervalMap, IntervalSet};
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fs;
+
+// Map of the almanac, lines has the line number of every range
+#[derive(Clone, Debug)]
+pub struct Map {
+  pub source: String,
+  pub destination: String,
+  pub ranges: IntervalMap,
+  pub lines: Vec<usize>
+}
+
+// Values above this limit are reported by the validator, the arithmetic of
+// the maps (offsets and compositions) has to stay below i64::MAX
+const NEAR_LIMIT: i128 = (i64::MAX / 2) as i128;
+
+// Parse the scale of a range, an integer or a fraction "numerator/denominator"
+// returned reduced and with a positive denominator
+fn parse_scale(token: &str) -> Result<(i128, i128), String> {
+  let invalid = || format!("Invalid scale '{}'", token);
+  let (numerator, denominator): (i128, i128) = match token.split_once('/') {
+    Some((numerator, denominator)) => {
+      (numerator.parse().map_err(|_| invalid())?, denominator.parse().map_err(|_| invalid())?)
+    },
+    None => (token.parse().map_err(|_| invalid())?, 1)
+  };
+  if denominator == 0 {
+    return Err(invalid());
+  }
+
+  let (mut a, mut b) = (numerator.abs(), denominator.abs());
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  let divisor = if denominator < 0 { -a.max(1) } else { a.max(1) };
+
+  Ok((numerator / divisor, denominator / divisor))
+}
+
+// Parse the numbers of a range line (wider than i64 to find the overflows),
+// the scale is optional and 1 by default
+fn parse_range_values(line: &str) -> Result<(i128, i128, i128, (i128, i128)), String> {
+  let tokens: Vec<&str> = line.split(' ').filter(|token| !token.is_empty()).collect();
+  if tokens.len() != 3 && tokens.len() != 4 {
+    return Err(format!("Expected 'destination source length [scale]', found '{}'", line));
+  }
+
+  let values: Vec<i128> = tokens[..3].iter()
+    .map(|token| token.parse().map_err(|_| format!("Invalid number '{}'", token)))
+    .collect::<Result<Vec<i128>, String>>()?;
+  let scale = match tokens.get(3) {
+    Some(token) => parse_scale(token)?,
+    None => (1, 1)
+  };
+
+  Ok((values[0], values[1], values[2], scale))
+}
+
+// Parse a range "destination source length [scale]", the values x of the source
+// go to destination + scale * (x - source) and with a negative scale the range
+// is reversed (the last value of the source goes to destination)
+fn parse_range(line: &str, map: &mut IntervalMap) -> Result<(), String> {
+  let (destination, origin, length, (numerator, denominator)) = parse_range_values(line)?;
+  if destination < 0 || origin < 0 || length < 0 {
+    return Err(format!("Negative value in '{}'", line));
+  }
+
+  // A single value goes to destination with any scale
+  let scale = if length <= 1 { 1 } else { numerator };
+  if denominator != 1 && length > 1 {
+    return Err(format!("Scale {}/{} does not map integers to integers ({} goes to {} + {}/{})",
+      numerator, denominator, origin + 1, destination, numerator, denominator));
+  }
+
+  let to_i64 = |value: i128| i64::try_from(value)
+    .map_err(|_| format!("Value {} past the integer limit {}", value, i64::MAX));
+  let first = if scale < 0 { origin + length - 1 } else { origin };
+  let destination_end = destination + scale.abs() * (length - 1).max(0) + 1;
+  let end = to_i64(origin + length)
+    .map_err(|_| format!("Source end {} + {} past the integer limit", origin, length))?;
+  to_i64(destination_end).map_err(|_| match scale {
+    1 => format!("Destination end {} + {} past the integer limit", destination, length),
+    _ => format!("Destination end {} + {} * ({} - 1) + 1 past the integer limit", destination, scale.abs(), length)
+  })?;
+  let scale = to_i64(scale)?;
+  let offset = destination - scale as i128 * first;
+
+  map.push_affine(Interval::new(to_i64(origin)?, end), scale, offset);
+
+  Ok(())
+}
+
+// Parse a header like "seed-to-soil map:" into the source and destination
+fn parse_header(line: &str) -> Result<(String, String), String> {
+  let name = match line.trim().strip_suffix("map:") {
+    Some(name) => name.trim(),
+    None => return Err(format!("Invalid map header '{}'", line))
+  };
+
+  match name.split_once("-to-") {
+    Some((source, destination)) if !source.is_empty() && !destination.is_empty() => {
+      Ok((source.to_string(), destination.to_string()))
+    },
+    _ => Err(format!("Invalid map name '{}', expected <source>-to-<destination>", name))
+  }
+}
+
+// Parse the maps of the almanac (the lines after the seeds), the lines with
+// errors are skipped (with the ranges of a wrong header) and returned with
+// their line numbers
+fn parse_maps_lines(input: &str) -> (Vec<Map>, Vec<(usize, String)>) {
+  let mut maps: Vec<Map> = Vec::new();
+  let mut errors: Vec<(usize, String)> = Vec::new();
+  let mut header_lines: HashMap<(String, String), usize> = HashMap::new();
+  let mut seeds = true;
+  let mut skip_ranges = false;
+
+  for (n, line) in input.lines().enumerate() {
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    if seeds {
+      seeds = false;
+      continue;
+    }
+
+    if line.contains("map:") {
+      let (source, destination) = match parse_header(line) {
+        Ok(header) => header,
+        Err(error) => {
+          errors.push((n + 1, error));
+          skip_ranges = true;
+          continue;
+        }
+      };
+      let key = (source.clone(), destination.clone());
+      if let Some(first_line) = header_lines.get(&key) {
+        errors.push((n + 1, format!("duplicated map {}-to-{} (first in line {})", source, destination, first_line)));
+        skip_ranges = true;
+        continue;
+      }
+
+      header_lines.insert(key, n + 1);
+      maps.push(Map { source, destination, ranges: IntervalMap::new(), lines: Vec::new() });
+      skip_ranges = false;
+      continue;
+    }
+
+    if skip_ranges {
+      continue;
+    }
+
+    match maps.last_mut() {
+      Some(map) => match parse_range(line, &mut map.ranges) {
+        Ok(()) => map.lines.push(n + 1),
+        Err(error) => errors.push((n + 1, error))
+      },
+      None => errors.push((n + 1, "range before any map header".to_string()))
+    }
+  }
+
+  (maps, errors)
+}
+
+// Parse the maps of the almanac, the error is the first one of the input
+pub fn parse_maps(input: &str) -> Result<Vec<Map>, String> {
+  let (maps, errors) = parse_maps_lines(input);
+  match errors.first() {
+    Some((line, error)) => Err(format!("Line {}: {}", line, error)),
+    None => Ok(maps)
+  }
+}
+
+// Checks that the category graph has no cycles
+fn check_cycles(maps: &[Map]) -> Result<(), String> {
+  // 0 not visited, 1 in the current path, 2 done
+  let mut state: HashMap<&str, u8> = HashMap::new();
+  let mut path: Vec<&str> = Vec::new();
+
+  fn visit<'a>(category: &'a str, maps: &'a [Map], state: &mut HashMap<&'a str, u8>, path: &mut Vec<&'a str>) -> Result<(), String> {
+    match state.get(category) {
+      Some(1) => {
+        let begin = path.iter().position(|step| *step == category).unwrap();
+        let mut cycle = path[begin..].to_vec();
+        cycle.push(category);
+        return Err(format!("Cycle in the categories: {}", cycle.join(" -> ")));
+      },
+      Some(2) => return Ok(()),
+      _ => ()
+    }
+
+    state.insert(category, 1);
+    path.push(category);
+    for map in maps.iter().filter(|map| map.source == category) {
+      visit(&map.destination, maps, state, path)?;
+    }
+    path.pop();
+    state.insert(category, 2);
+
+    Ok(())
+  }
+
+  for map in maps {
+    visit(&map.source, maps, &mut state, &mut path)?;
+  }
+
+  Ok(())
+}
+
+// Maps to go from a category to another one following the category graph
+pub fn find_path<'a>(maps: &'a [Map], from: &str, to: &str) -> Result<Vec<&'a Map>, String> {
+  check_cycles(maps)?;
+
+  let mut previous: HashMap<&str, &Map> = HashMap::new();
+  let mut queue: VecDeque<&str> = VecDeque::from([from]);
+  while let Some(category) = queue.pop_front() {
+    if category == to {
+      break;
+    }
+
+    for map in maps.iter().filter(|map| map.source == category) {
+      if map.destination != from && !previous.contains_key(map.destination.as_str()) {
+        previous.insert(&map.destination, map);
+        queue.push_back(&map.destination);
+      }
+    }
+  }
+
+  if from != to && !previous.contains_key(to) {
+    let mut reachable: Vec<&str> = previous.keys().cloned().collect();
+    reachable.sort();
+    return Err(format!("Missing link: no maps from {} to {} (reachable from {}: {})",
+      from, to, from, if reachable.is_empty() { "nothing".to_string() } else { reachable.join(", ") }));
+  }
+
+  let mut path: Vec<&Map> = Vec::new();
+  let mut category = to;
+  while category != from {
+    let map = previous[category];
+    path.push(map);
+    category = &map.source;
+  }
+  path.reverse();
+
+  Ok(path)
+}
+
+// Compose the maps of a path into one map
+pub fn compose_maps(path: &[&Map], from: &str, to: &str) -> Result<Map, String> {
+  let mut ranges = IntervalMap::new();
+  for map in path {
+    ranges = ranges.compose(&map.ranges)?;
+  }
+
+  Ok(Map { source: from.to_string(), destination: to.to_string(), ranges, lines: Vec::new() })
+}
+
+// Step of a trace, a piece of the input of a map with the line of the range
+// applied to it (None when the values map to themselves)
+#[derive(Clone, Debug)]
+pub struct TraceStep {
+  pub stage: usize,
+  pub source: String,
+  pub destination: String,
+  pub input: Interval,
+  pub line: Option<usize>,
+  pub output: Vec<Interval>
+}
+
+// Follows the seeds through the maps of the path, the input of every map is
+// split by the ranges that match it (the first one wins like in the lookups)
+pub fn trace(path: &[&Map], seeds: &IntervalSet) -> Result<Vec<TraceStep>, String> {
+  let mut steps: Vec<TraceStep> = Vec::new();
+  let mut values = seeds.clone();
+  for (stage, map) in path.iter().enumerate() {
+    let mut stage_steps: Vec<TraceStep> = Vec::new();
+    let mut step = |input: Interval, line: Option<usize>, output: Vec<Interval>| stage_steps.push(TraceStep {
+      stage: stage + 1, source: map.source.clone(), destination: map.destination.clone(), input, line, output
+    });
+
+    let mut rest = values.clone();
+    for (range, line) in map.ranges.ranges.iter().zip(map.lines.iter()) {
+      let matched = rest.intersection(&IntervalSet::from_intervals(vec![range.source]));
+      for interval in matched.intervals() {
+        step(*interval, Some(*line), range.image(interval)?);
+      }
+      rest = rest.difference(&matched);
+    }
+    for interval in rest.intervals() {
+      step(*interval, None, vec![*interval]);
+    }
+
+    stage_steps.sort_by_key(|step| step.input);
+    values = IntervalSet::from_intervals(stage_steps.iter().flat_map(|step| step.output.clone()).collect());
+    steps.extend(stage_steps);
+  }
+
+  Ok(steps)
+}
+
+// A single value or "begin..end"
+fn interval_text(interval: &Interval) -> String {
+  if interval.len() == 1 { interval.begin.to_string() } else { interval.to_string() }
+}
+
+fn intervals_text(intervals: &[Interval], separator: &str) -> String {
+  intervals.iter().map(interval_text).collect::<Vec<String>>().join(separator)
+}
+
+// Table with the steps of a trace
+pub fn trace_table(steps: &[TraceStep]) -> String {
+  let mut table = String::from("Stage | Category | Input | Line | Output\n");
+  for step in steps {
+    let line = match step.line {
+      Some(line) => line.to_string(),
+      None => "identity".to_string()
+    };
+
+    table += &format!("{} | {}-to-{} | {} | {} | {}\n", step.stage, step.source, step.destination,
+      interval_text(&step.input), line, intervals_text(&step.output, ", "));
+  }
+
+  table
+}
+
+// JSON with the steps of a trace, the intervals are [begin, end) pairs
+pub fn trace_json(steps: &[TraceStep]) -> String {
+  let pair = |interval: &Interval| format!("[{}, {}]", interval.begin, interval.end);
+  let steps: Vec<String> = steps.iter().map(|step| {
+    let line = match step.line {
+      Some(line) => line.to_string(),
+      None => "null".to_string()
+    };
+    let output: Vec<String> = step.output.iter().map(pair).collect();
+
+    format!("  {{\"stage\": {}, \"source\": \"{}\", \"destination\": \"{}\", \"input\": {}, \"line\": {}, \"output\": [{}]}}",
+      step.stage, step.source, step.destination, pair(&step.input), line, output.join(", "))
+  }).collect();
+
+  format!("[\n{}\n]\n", steps.join(",\n"))
+}
+
+// Equivalent almanac with every map normalized (sorted ranges without the
+// identity ones and the contiguous ranges of the same offset merged), with a
+// (from, to) pair the maps of that path are collapsed into one
+pub fn simplify(input: &str, collapse: Option<(&str, &str)>) -> Result<String, String> {
+  let seeds_line = input.lines().find(|line| !line.trim().is_empty()).unwrap_or("seeds:");
+  let maps = parse_maps(input)?;
+
+  let mut simplified: Vec<Map> = Vec::new();
+  let mut collapsed: Vec<(&str, &str)> = Vec::new();
+  if let Some((from, to)) = collapse {
+    let path = find_path(&maps, from, to)?;
+    collapsed = path.iter().map(|map| (map.source.as_str(), map.destination.as_str())).collect();
+    simplified.push(compose_maps(&path, from, to)?);
+  }
+
+  for map in maps.iter() {
+    if !collapsed.contains(&(map.source.as_str(), map.destination.as_str())) {
+      let ranges = map.ranges.normalized();
+      simplified.push(Map { source: map.source.clone(), destination: map.destination.clone(), ranges, lines: Vec::new() });
+    }
+  }
+
+  let mut output = format!("{}\n", seeds_line);
+  for map in simplified.iter() {
+    output += &format!("\n{}-to-{} map:\n{}", map.source, map.destination, map.ranges.to_almanac());
+  }
+
+  Ok(output)
+}
+
+// Checks that every seed goes to the same value with both maps, between two
+// range bounds both maps are affine so checking the first and the last seed
+// of every piece is enough
+pub fn check_equivalent(before: &Map, after: &Map, seeds: &IntervalSet) -> Result<usize, String> {
+  let mut bounds: Vec<i64> = Vec::new();
+  for interval in seeds.intervals() {
+    bounds.push(interval.begin);
+    bounds.push(interval.end);
+  }
+  for map in [before, after] {
+    for range in map.ranges.normalized().ranges.iter() {
+      bounds.push(range.source.begin);
+      bounds.push(range.source.end);
+    }
+  }
+  bounds.sort();
+  bounds.dedup();
+
+  let mut points: Vec<i64> = Vec::new();
+  for pair in bounds.windows(2) {
+    if seeds.contains(pair[0]) {
+      points.push(pair[0]);
+      if pair[1] - 1 > pair[0] {
+        points.push(pair[1] - 1);
+      }
+    }
+  }
+
+  for point in points.iter() {
+    let (expected, found) = (before.ranges.get(*point), after.ranges.get(*point));
+    if expected != found {
+      return Err(format!("{} {} goes to {} {} before simplifying and to {} after",
+        before.source, point, before.destination, expected, found));
+    }
+  }
+
+  Ok(points.len())
+}
+
+// Smallest destination of the seeds, they move through the maps of the path
+// one stage at a time, every map is normalized once and the intervals of each
+// stage are merged. A scale above 1 breaks the intervals, then the maps are
+// composed and only the bounds of the seed intervals are mapped
+pub fn min_destination(seeds: &IntervalSet, path: &[&Map]) -> Result<i64, String> {
+  let stages: Vec<IntervalMap> = path.iter().map(|map| map.ranges.normalized()).collect();
+  if !stages.iter().all(|stage| stage.keeps_intervals()) {
+    let location_map = stages.iter().try_fold(IntervalMap::new(), |map, stage| map.compose(stage))?;
+    return location_map.min_image(seeds).ok_or("No seeds".to_string());
+  }
+
+  let mut values = seeds.clone();
+  for stage in stages.iter() {
+    values = stage.map_set_sorted(&values)?;
+  }
+
+  values.min().ok_or("No seeds".to_string())
+}
+
+// Checks the almanac, reports the lines that can't be parsed, the seed values
+// out of the limits or without a length and in the ranges that can
+// overlapping source ranges in the same map, zero length ranges and values
+// near the integer limits
+pub fn validate(input: &str) -> Vec<String> {
+  let (maps, mut issues) = parse_maps_lines(input);
+
+  for (n, line) in input.lines().enumerate() {
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    // The part 2 reads the seeds as pairs of start and length
+    let values: Vec<&str> = line.split(' ').skip(1).filter(|token| !token.is_empty()).collect();
+    if values.len() % 2 == 1 {
+      issues.push((n + 1, format!("seed range {} without a length", values[values.len() - 1])));
+    }
+
+    for token in values {
+      match token.parse::<i128>() {
+        Ok(value) if value < 0 || value >= i64::MAX as i128 => {
+          issues.push((n + 1, format!("seed value {} past the integer limits", value)));
+        },
+        Ok(value) if value > NEAR_LIMIT => {
+          issues.push((n + 1, format!("seed value {} near the integer limit", value)));
+        },
+        Ok(_) => (),
+        Err(_) => issues.push((n + 1, format!("invalid seed value '{}'", token)))
+      }
+    }
+    break;
+  }
+
+  for map in maps.iter() {
+    for (i, (range, line)) in map.ranges.ranges.iter().zip(map.lines.iter()).enumerate() {
+      if range.source.is_empty() {
+        issues.push((*line, format!("zero length range in {}-to-{}", map.source, map.destination)));
+        continue;
+      }
+
+      // Largest value of the range, the source end or the destination end
+      let image = [range.source.begin, range.source.end - 1]
+        .map(|point| range.apply_wide(point));
+      let max_value = (range.source.end as i128).max(image[0].max(image[1]) + 1);
+      if max_value > NEAR_LIMIT {
+        issues.push((*line, format!("values near the integer limit (up to {})", max_value)));
+      }
+
+      for (other, other_line) in map.ranges.ranges[..i].iter().zip(map.lines.iter()) {
+        if !range.source.intersect(&other.source).is_empty() {
+          issues.push((*line, format!("source range {} overlaps line {} ({}) in {}-to-{}",
+            range.source, other_line, other.source, map.source, map.destination)));
+        }
+      }
+    }
+  }
+
+  issues.sort_by_key(|(line, _)| *line);
+  issues.into_iter().map(|(line, issue)| format!("Line {}: {}", line, issue)).collect()
+}
+
+// Almanac with the seeds of the input and the composed map
+fn composed_almanac(input: &str, location_map: &Map) -> String {
+  let seeds_line = input.lines().find(|line| !line.is_empty()).unwrap_or("seeds:");
+
+  format!("{}\n\n{}-to-{} map:\n{}", seeds_line,
+    location_map.source, location_map.destination, location_map.ranges.to_almanac())
+}
+
+// Seeds that end in the locations, only the ones in the seeds set if any
+fn find_seeds(locations: &IntervalSet, location_map: &Map, seeds: Option<&IntervalSet>) -> IntervalSet {
+  let result = location_map.ranges.preimage(locations);
+  match seeds {
+    Some(seeds) => result.intersection(seeds),
+    None => result
+  }
+}
+
+// Print the n lowest locations of the seeds and the seeds of each one
+fn print_lowest_locations(seeds: &IntervalSet, location_map: &Map, n: i64) -> Result<(), String> {
+  let locations = location_map.ranges.map_set(seeds)?;
+  for i in 0..n {
+    let location = match locations.nth(i) {
+      Some(location) => location,
+      None => break
+    };
+
+    let location_set = IntervalSet::from_intervals(vec![Interval::new(location, location + 1)]);
+    let location_seeds = find_seeds(&location_set, location_map, Some(seeds));
+    println!("Location #{} {}: seeds {}", i + 1, location, location_seeds);
+  }
+
+  Ok(())
+}
+
+// Checks the simplified almanac against the original one
+fn check_simplified(input: &str, seeds: &IntervalSet, path: &[&Map], from: &str, to: &str, collapse: bool) -> Result<usize, String> {
+  let simplified = simplify(input, if collapse { Some((from, to)) } else { None })?;
+  let simplified_maps = parse_maps(&simplified)?;
+  let simplified_path = find_path(&simplified_maps, from, to)?;
+
+  let before = compose_maps(path, from, to)?;
+  let after = compose_maps(&simplified_path, from, to)?;
+  check_equivalent(&before, &after, seeds)
+}
+
+// Splits the arguments after the file name in the categories of the chain,
+// "--from <category>" and "--to <category>" (seed and location by default),
+// and the options
+pub fn parse_arguments(args: &[String]) -> (String, String, Vec<String>) {
+  let mut from = "seed".to_string();
+  let mut to = "location".to_string();
+  let mut options: Vec<String> = Vec::new();
+  let mut i = 2usize;
+  while i < args.len() {
+    if (args[i] == "--from" || args[i] == "--to") && i + 1 < args.len() {
+      if args[i] == "--from" {
+        from = args[i + 1].clone();
+      } else {
+        to = args[i + 1].clone();
+      }
+      i += 2;
+      continue;
+    }
+
+    options.push(args[i].clone());
+    i += 1;
+  }
+
+  (from, to, options)
+}
+
+// Runs the options that only need the text of the almanac, returns false
+// when the options don't have one of them
+pub fn run_input_command(input: &str, from: &str, to: &str, options: &[String]) -> bool {
+  // "--validate" reports the problems of the almanac
+  if !options.is_empty() && options[0] == "--validate" {
+    let issues = validate(input);
+    for issue in issues.iter() {
+      println!("{}", issue);
+    }
+    println!("{} issues found", issues.len());
+    return true;
+  }
+
+  // "--simplify [--collapse] [output]" writes an equivalent almanac with
+  // simpler maps, "--collapse" composes the maps of the chain into one
+  let collapse = options.len() > 1 && options[1] == "--collapse";
+  if !options.is_empty() && options[0] == "--simplify" {
+    let output = match simplify(input, if collapse { Some((from, to)) } else { None }) {
+      Ok(output) => output,
+      Err(error) => {
+        println!("Error: {}", error);
+        return true;
+      }
+    };
+
+    let output_index = if collapse { 2 } else { 1 };
+    if options.len() > output_index {
+      fs::write(&options[output_index], output).expect("Error writing the file");
+    } else {
+      print!("{}", output);
+    }
+    return true;
+  }
+
+  false
+}
+
+// Runs the options that follow the seeds through the path, returns false
+// when the options don't have one of them
+pub fn run_path_command(input: &str, seeds: &IntervalSet, path: &[&Map], from: &str, to: &str, options: &[String]) -> bool {
+  // "--check-simplify [--collapse]" checks that every seed goes to the same
+  // value with the simplified almanac
+  if !options.is_empty() && options[0] == "--check-simplify" {
+    let collapse = options.len() > 1 && options[1] == "--collapse";
+    match check_simplified(input, seeds, path, from, to, collapse) {
+      Ok(points) => println!("Simplified almanac ok ({} points checked)", points),
+      Err(error) => {
+        println!("{}", error);
+        std::process::exit(1);
+      }
+    }
+    return true;
+  }
+
+  // "--trace <seed|begin..end|all> [table|json]" follows the seeds through
+  // every map, "all" traces the seeds of the input
+  if options.len() > 1 && options[0] == "--trace" {
+    let traced = match Interval::parse(&options[1]) {
+      Some(interval) => IntervalSet::from_intervals(vec![interval]),
+      None if options[1] == "all" => seeds.clone(),
+      None => {
+        println!("Invalid seeds '{}', expected a seed, begin..end or all", options[1]);
+        return true;
+      }
+    };
+
+    let steps = match trace(path, &traced) {
+      Ok(steps) => steps,
+      Err(error) => {
+        println!("Error: {}", error);
+        return true;
+      }
+    };
+    match options.get(2).map(|format| format.as_str()) {
+      None | Some("table") => print!("{}", trace_table(&steps)),
+      Some("json") => print!("{}", trace_json(&steps)),
+      Some(format) => println!("Invalid trace format '{}', expected table or json", format)
+    }
+    return true;
+  }
+
+  // "--query <seed|begin..end>" prints where the seeds end with the maps
+  // composed in one, without going through the maps of the path
+  if options.len() > 1 && options[0] == "--query" {
+    let queried = match Interval::parse(&options[1]) {
+      Some(interval) => interval,
+      None => {
+        println!("Invalid seeds '{}', expected a seed or begin..end", options[1]);
+        return true;
+      }
+    };
+
+    let location_map = match compose_maps(path, from, to) {
+      Ok(location_map) => location_map,
+      Err(error) => {
+        println!("Error: {}", error);
+        return true;
+      }
+    };
+    if queried.len() == 1 {
+      println!("{} {} goes to {} {}", from, queried.begin, to, location_map.ranges.get(queried.begin));
+      return true;
+    }
+    match location_map.ranges.map_set(&IntervalSet::from_intervals(vec![queried])) {
+      Ok(values) => println!("{} {} go to {} {}", from, queried, to, values),
+      Err(error) => println!("Error: {}", error)
+    }
+    return true;
+  }
+
+  // "--print-composed" prints the almanac with the maps composed in one
+  if !options.is_empty() && options[0] == "--print-composed" {
+    let location_map = match compose_maps(path, from, to) {
+      Ok(location_map) => location_map,
+      Err(error) => {
+        println!("Error: {}", error);
+        return true;
+      }
+    };
+    print!("{}", composed_almanac(input, &location_map));
+    return true;
+  }
+
+  // "--inverse <begin..end> [--all-seeds]" prints the seeds that end in the
+  // locations and "--rank <n>" the n lowest locations with their seeds
+  if options.len() > 1 && options[0] == "--inverse" {
+    let locations = match Interval::parse(&options[1]) {
+      Some(locations) => IntervalSet::from_intervals(vec![locations]),
+      None => {
+        println!("Invalid locations '{}', expected begin..end or a single location", options[1]);
+        return true;
+      }
+    };
+
+    let all_seeds = options.len() > 2 && options[2] == "--all-seeds";
+    let limit = if all_seeds { None } else { Some(seeds) };
+    let location_map = match compose_maps(path, from, to) {
+      Ok(location_map) => location_map,
+      Err(error) => {
+        println!("Error: {}", error);
+        return true;
+      }
+    };
+    println!("Seeds {}", find_seeds(&locations, &location_map, limit));
+    return true;
+  }
+
+  if options.len() > 1 && options[0] == "--rank" {
+    let location_map = match compose_maps(path, from, to) {
+      Ok(location_map) => location_map,
+      Err(error) => {
+        println!("Error: {}", error);
+        return true;
+      }
+    };
+    match options[1].parse() {
+      Ok(n) => if let Err(error) = print_lowest_locations(seeds, &location_map, n) {
+        println!("Error: {}", error);
+      },
+      Err(_) => println!("Invalid number of locations '{}'", options[1])
+    }
+    return true;
+  }
+
+  false
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Xorshift generator, the tests don't need a good one
+  fn random(state: &mut u64, max: i64) -> i64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state % max as u64) as i64
+  }
+
+  // Almanac with a chain of maps with overlapping, identity, contiguous and
+  // scaled ranges over the values 0..200
+  fn random_almanac(state: &mut u64) -> String {
+    let categories = ["seed", "soil", "water", "light", "location"];
+    let mut almanac = String::from("seeds: 0 200\n");
+    for pair in categories.windows(2) {
+      almanac += &format!("\n{}-to-{} map:\n", pair[0], pair[1]);
+      for _ in 0..random(state, 6) {
+        let (source, length) = (random(state, 200), random(state, 30));
+        let destination = match random(state, 4) {
+          0 => source,
+          _ => random(state, 200)
+        };
+        let scale = match random(state, 5) {
+          0 => " -1",
+          1 => " 2",
+          _ => ""
+        };
+        almanac += &format!("{} {} {}{}\n", destination, source, length, scale);
+      }
+    }
+
+    almanac
+  }
+
+  // Almanac of the puzzle example
+  const EXAMPLE: &str = "\
+seeds: 79 14 55 13\n\
+\n\
+seed-to-soil map:\n\
+50 98 2\n\
+52 50 48\n\
+\n\
+soil-to-fertilizer map:\n\
+0 15 37\n\
+37 52 2\n\
+39 0 15\n\
+\n\
+fertilizer-to-water map:\n\
+49 53 8\n\
+0 11 42\n\
+42 0 7\n\
+57 7 4\n\
+\n\
+water-to-light map:\n\
+88 18 7\n\
+18 25 70\n\
+\n\
+light-to-temperature map:\n\
+45 77 23\n\
+81 45 19\n\
+68 64 13\n\
+\n\
+temperature-to-humidity map:\n\
+0 69 1\n\
+1 0 69\n\
+\n\
+humidity-to-location map:\n\
+60 56 37\n\
+56 93 4\n";
+
+  fn destination(path: &[&Map], seed: i64) -> i64 {
+    path.iter().fold(seed, |value, map| map.ranges.get(value))
+  }
+
+  #[test]
+  fn simplify_keeps_every_seed() {
+    let mut state = 88172645463325252u64;
+    for _ in 0..300 {
+      let almanac = random_almanac(&mut state);
+      let maps = parse_maps(&almanac).unwrap();
+      let path = find_path(&maps, "seed", "location").unwrap();
+
+      for collapse in [None, Some(("seed", "location"))] {
+        let simplified = simplify(&almanac, collapse).unwrap();
+        let simplified_maps = parse_maps(&simplified).unwrap();
+        let simplified_path = find_path(&simplified_maps, "seed", "location").unwrap();
+        assert!(simplified.starts_with("seeds: 0 200\n"));
+
+        for seed in 0..200 {
+          assert_eq!(destination(&simplified_path, seed), destination(&path, seed),
+            "seed {} in\n{}\nsimplified to\n{}", seed, almanac, simplified);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn check_equivalent_finds_different_affine_maps() {
+    let seeds = IntervalSet::from_intervals(vec![Interval::new(0, 10)]);
+    let before = &parse_maps("seeds: 0 10\n\nseed-to-location map:\n0 0 10 -1\n").unwrap()[0];
+    let after = &parse_maps("seeds: 0 10\n\nseed-to-location map:\n9 0 10\n").unwrap()[0];
+    assert!(check_equivalent(before, after, &seeds).is_err());
+    assert_eq!(check_equivalent(before, before, &seeds), Ok(2));
+  }
+
+  #[test]
+  fn composed_map_answers_queries() {
+    let maps = parse_maps(EXAMPLE).unwrap();
+    let path = find_path(&maps, "seed", "location").unwrap();
+    let location_map = compose_maps(&path, "seed", "location").unwrap();
+    for (seed, location) in [(79, 82), (14, 43), (55, 86), (13, 35)] {
+      assert_eq!(location_map.ranges.get(seed), location);
+    }
+
+    let seeds = IntervalSet::from_intervals(vec![Interval::new(79, 93), Interval::new(55, 68)]);
+    assert_eq!(location_map.ranges.map_set(&seeds).unwrap().min(), Some(46));
+  }
+
+  #[test]
+  fn duplicated_maps() {
+    let almanac = EXAMPLE.replace("soil-to-fertilizer", "seed-to-soil");
+    assert_eq!(parse_maps(&almanac).unwrap_err(), "Line 7: duplicated map seed-to-soil (first in line 3)");
+    assert!(validate(&almanac).contains(&"Line 7: duplicated map seed-to-soil (first in line 3)".to_string()));
+  }
+
+  #[test]
+  fn shuffled_maps_follow_the_headers() {
+    let sections: Vec<&str> = EXAMPLE.split("\n\n").collect();
+    let mut shuffled = sections[1..].to_vec();
+    shuffled.reverse();
+    let almanac = format!("{}\n\n{}", sections[0], shuffled.join("\n\n"));
+    let maps = parse_maps(&almanac).unwrap();
+    let path = find_path(&maps, "seed", "location").unwrap();
+    let categories: Vec<&str> = path.iter().map(|map| map.source.as_str()).collect();
+    assert_eq!(categories, ["seed", "soil", "fertilizer", "water", "light", "temperature", "humidity"]);
+    assert_eq!(destination(&path, 79), 82);
+
+    let path = find_path(&maps, "soil", "water").unwrap();
+    assert_eq!(path.len(), 2);
+    assert!(find_path(&maps, "seed", "seed").unwrap().is_empty());
+  }
+
+  #[test]
+  fn missing_links() {
+    let almanac = EXAMPLE.replace("water-to-light", "water-to-sunlight");
+    let maps = parse_maps(&almanac).unwrap();
+    assert_eq!(find_path(&maps, "seed", "location").unwrap_err(),
+      "Missing link: no maps from seed to location (reachable from seed: fertilizer, soil, sunlight, water)");
+    assert_eq!(find_path(&maps, "location", "seed").unwrap_err(),
+      "Missing link: no maps from location to seed (reachable from location: nothing)");
+  }
+
+  #[test]
+  fn cycles() {
+    let almanac = format!("{}\nlocation-to-soil map:\n0 0 1\n", EXAMPLE);
+    let maps = parse_maps(&almanac).unwrap();
+    assert_eq!(find_path(&maps, "seed", "location").unwrap_err(),
+      "Cycle in the categories: soil -> fertilizer -> water -> light -> temperature -> humidity -> location -> soil");
+  }
+
+  #[test]
+  fn inverse_lookup() {
+    let maps = parse_maps(EXAMPLE).unwrap();
+    let path = find_path(&maps, "seed", "location").unwrap();
+    let location_map = compose_maps(&path, "seed", "location").unwrap();
+    let seeds = IntervalSet::from_intervals(vec![Interval::new(79, 93), Interval::new(55, 68)]);
+    let locations = IntervalSet::from_intervals(vec![Interval::new(46, 47)]);
+    assert_eq!(find_seeds(&locations, &location_map, Some(&seeds)).to_string(), "{82..83}");
+
+    // Every seed that ends in the locations, also the ones out of the input
+    let locations = IntervalSet::from_intervals(vec![Interval::new(0, 100)]);
+    let all_seeds = find_seeds(&locations, &location_map, None);
+    for seed in 0..200 {
+      assert_eq!(all_seeds.contains(seed), destination(&path, seed) < 100, "seed {}", seed);
+    }
+  }
+
+  #[test]
+  fn seed_line_issues() {
+    assert_eq!(validate("seeds: 79 14 55\n"), ["Line 1: seed range 55 without a length"]);
+    assert_eq!(validate("seeds: 9223372036854775807 1\n"),
+      ["Line 1: seed value 9223372036854775807 past the integer limits"]);
+    assert!(validate(EXAMPLE).is_empty());
+  }
+
+  // Almanac with a chain of depth maps of up to ranges ranges each over the
+  // values 0..500, the seeds are a few random intervals
+  fn deep_almanac(state: &mut u64, depth: usize, ranges: i64, scaled: bool) -> (String, IntervalSet) {
+    let mut seeds: Vec<Interval> = Vec::new();
+    let mut almanac = String::from("seeds:");
+    for _ in 0..1 + random(state, 4) {
+      let (begin, length) = (random(state, 500), 1 + random(state, 100));
+      seeds.push(Interval::new(begin, begin + length));
+      almanac += &format!(" {} {}", begin, length);
+    }
+    almanac += "\n";
+
+    for stage in 0..depth {
+      let source = if stage == 0 { "seed".to_string() } else { format!("c{}", stage) };
+      let destination = if stage == depth - 1 { "location".to_string() } else { format!("c{}", stage + 1) };
+      almanac += &format!("\n{}-to-{} map:\n", source, destination);
+      for _ in 0..random(state, ranges + 1) {
+        let scale = match random(state, 6) {
+          0 => " -1",
+          1 if scaled => " 2",
+          _ => ""
+        };
+        almanac += &format!("{} {} {}{}\n", random(state, 500), random(state, 500), random(state, 80), scale);
+      }
+    }
+
+    (almanac, IntervalSet::from_intervals(seeds))
+  }
+
+  #[test]
+  fn min_destination_of_deep_almanacs() {
+    let mut state = 2463534242u64;
+    for (depth, ranges, scaled) in [(12, 20, false), (25, 8, false), (10, 15, true)] {
+      for _ in 0..40 {
+        let (almanac, seeds) = deep_almanac(&mut state, depth, ranges, scaled);
+        let maps = parse_maps(&almanac).unwrap();
+        let path = find_path(&maps, "seed", "location").unwrap();
+        let expected = seeds.intervals().iter()
+          .flat_map(|interval| interval.begin..interval.end)
+          .map(|seed| destination(&path, seed))
+          .min();
+        assert_eq!(min_destination(&seeds, &path).ok(), expected, "in\n{}", almanac);
+      }
+    }
+  }
+
+  #[test]
+  fn compose_single_values_with_large_scales() {
+    let almanac = "seeds: 0 2\n\nseed-to-soil map:\n0 0 2 1099511627776\n\nsoil-to-location map:\n0 0 2 1099511627776\n";
+    assert!(validate(almanac).is_empty());
+    let maps = parse_maps(almanac).unwrap();
+    let path = find_path(&maps, "seed", "location").unwrap();
+    let seeds = IntervalSet::from_intervals(vec![Interval::new(0, 2)]);
+    assert_eq!(min_destination(&seeds, &path), Ok(0));
+
+    let location_map = compose_maps(&path, "seed", "location").unwrap();
+    for seed in 0..3 {
+      assert_eq!(location_map.ranges.get(seed), destination(&path, seed));
+    }
+
+    // Two values of the same piece 2^64 apart don't fit in the integers
+    let mut map = IntervalMap::new();
+    map.push_affine(Interval::new(0, 1 << 40), 1 << 32, 0);
+    assert!(map.compose(&map).unwrap_err().starts_with("Overflow composing"));
+  }
+}
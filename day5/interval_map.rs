@@ -0,0 +1,478 @@
+// Interval maps shared by both parts of the almanac
+
+use std::fmt;
+
+fn checked_offset(value: i64, offset: i64) -> i64 {
+  value.checked_add(offset)
+    .unwrap_or_else(|| panic!("Overflow applying offset {} to {}", offset, value))
+}
+
+// Most values listed for the image of an interval with a scale above 1, the
+// image is not an interval and it has one value per value of the source
+pub const MAX_SCALED_VALUES: i64 = 100_000;
+
+// Integer division rounding down (or up) also with negative values
+fn floor_div(numerator: i128, denominator: i128) -> i128 {
+  let quotient = numerator / denominator;
+  if numerator % denominator != 0 && (numerator < 0) != (denominator < 0) { quotient - 1 } else { quotient }
+}
+
+fn ceil_div(numerator: i128, denominator: i128) -> i128 {
+  -floor_div(-numerator, denominator)
+}
+
+// Half-open interval of integers [begin, end), empty when begin >= end
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Interval {
+  pub begin: i64,
+  pub end: i64
+}
+
+impl Interval {
+  pub fn new(begin: i64, end: i64) -> Interval {
+    Interval { begin, end }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.begin >= self.end
+  }
+
+  pub fn len(&self) -> i64 {
+    if self.is_empty() { 0 } else { self.end - self.begin }
+  }
+
+  pub fn contains(&self, point: i64) -> bool {
+    point >= self.begin && point < self.end
+  }
+
+  // Intersection of two intervals (can be empty)
+  pub fn intersect(&self, other: &Interval) -> Interval {
+    Interval {
+      begin: self.begin.max(other.begin),
+      end: self.end.min(other.end)
+    }
+  }
+
+  // Parse "begin..end" (end not included) or a single value
+  pub fn parse(text: &str) -> Option<Interval> {
+    let interval = match text.split_once("..") {
+      Some((begin, end)) => Interval::new(begin.trim().parse().ok()?, end.trim().parse().ok()?),
+      None => {
+        let value: i64 = text.trim().parse().ok()?;
+        Interval::new(value, value + 1)
+      }
+    };
+
+    if interval.is_empty() { None } else { Some(interval) }
+  }
+}
+
+impl fmt::Display for Interval {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}..{}", self.begin, self.end)
+  }
+}
+
+// Set of integers as a sorted list of disjoint intervals, two intervals of the
+// list never overlap or touch (they are merged)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+  intervals: Vec<Interval>
+}
+
+impl IntervalSet {
+  pub fn new() -> IntervalSet {
+    IntervalSet { intervals: Vec::new() }
+  }
+
+  // Build a set from any list of intervals, sorting and merging them
+  pub fn from_intervals(mut intervals: Vec<Interval>) -> IntervalSet {
+    intervals.retain(|interval| !interval.is_empty());
+    intervals.sort();
+
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for interval in intervals {
+      match merged.last_mut() {
+        Some(last) if interval.begin <= last.end => last.end = last.end.max(interval.end),
+        _ => merged.push(interval)
+      }
+    }
+
+    IntervalSet { intervals: merged }
+  }
+
+  pub fn intervals(&self) -> &Vec<Interval> {
+    &self.intervals
+  }
+
+  pub fn min(&self) -> Option<i64> {
+    self.intervals.first().map(|interval| interval.begin)
+  }
+
+  // The n-th smallest value of the set (starting at 0)
+  pub fn nth(&self, mut n: i64) -> Option<i64> {
+    for interval in self.intervals.iter() {
+      if n < interval.len() {
+        return Some(interval.begin + n);
+      }
+      n -= interval.len();
+    }
+
+    None
+  }
+
+  pub fn contains(&self, point: i64) -> bool {
+    let pos = self.intervals.partition_point(|interval| interval.end <= point);
+    pos < self.intervals.len() && self.intervals[pos].contains(point)
+  }
+
+  pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+    let mut intervals = self.intervals.clone();
+    intervals.extend(other.intervals.iter().cloned());
+
+    IntervalSet::from_intervals(intervals)
+  }
+
+  pub fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+    let mut intervals: Vec<Interval> = Vec::new();
+    let (mut i, mut j) = (0usize, 0usize);
+    while i < self.intervals.len() && j < other.intervals.len() {
+      let intersection = self.intervals[i].intersect(&other.intervals[j]);
+      if !intersection.is_empty() {
+        intervals.push(intersection);
+      }
+
+      if self.intervals[i].end < other.intervals[j].end {
+        i += 1;
+      } else {
+        j += 1;
+      }
+    }
+
+    IntervalSet { intervals }
+  }
+
+  pub fn difference(&self, other: &IntervalSet) -> IntervalSet {
+    let mut intervals: Vec<Interval> = Vec::new();
+    let mut j = 0usize;
+    for interval in self.intervals.iter() {
+      let mut begin = interval.begin;
+      while j < other.intervals.len() && other.intervals[j].end <= begin {
+        j += 1;
+      }
+
+      let mut k = j;
+      while k < other.intervals.len() && other.intervals[k].begin < interval.end {
+        if other.intervals[k].begin > begin {
+          intervals.push(Interval::new(begin, other.intervals[k].begin));
+        }
+        begin = begin.max(other.intervals[k].end);
+        k += 1;
+      }
+
+      if begin < interval.end {
+        intervals.push(Interval::new(begin, interval.end));
+      }
+    }
+
+    IntervalSet { intervals }
+  }
+}
+
+impl fmt::Display for IntervalSet {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let intervals: Vec<String> = self.intervals.iter().map(|interval| interval.to_string()).collect();
+    write!(f, "{{{}}}", intervals.join(", "))
+  }
+}
+
+// Range of an interval map, the values x of source go to scale * x + offset
+// (scale is 1 for the plain offsets and -1 for the reversed ranges). The
+// offset is wider than the values because with a scale it can be far from
+// the images, like -2 * first for a range that doubles the values from first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapRange {
+  pub source: Interval,
+  pub scale: i64,
+  pub offset: i128
+}
+
+impl MapRange {
+  pub fn is_identity(&self) -> bool {
+    self.scale == 1 && self.offset == 0
+  }
+
+  // Image of a value without the i64 limits
+  pub fn apply_wide(&self, point: i64) -> i128 {
+    self.scale as i128 * point as i128 + self.offset
+  }
+
+  // Image of a value, the almanac parser keeps the images of the sources
+  // between 0 and i64::MAX so an overflow here is a bug
+  pub fn apply(&self, point: i64) -> i64 {
+    i64::try_from(self.apply_wide(point))
+      .unwrap_or_else(|_| panic!("Overflow applying {} * x + {} to {}", self.scale, self.offset, point))
+  }
+
+  // Image of an interval of the source, with a scale above 1 the image is not
+  // an interval and every value is listed (up to MAX_SCALED_VALUES)
+  pub fn image(&self, interval: &Interval) -> Result<Vec<Interval>, String> {
+    if interval.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let (first, last) = (self.apply(interval.begin), self.apply(interval.end - 1));
+    if self.scale.abs() <= 1 {
+      return Ok(vec![Interval::new(first.min(last), checked_offset(first.max(last), 1))]);
+    }
+
+    if interval.len() > MAX_SCALED_VALUES {
+      return Err(format!("The image of {} with scale {} has {} separate values (more than {})",
+        interval, self.scale, interval.len(), MAX_SCALED_VALUES));
+    }
+
+    Ok((interval.begin..interval.end)
+      .map(|point| self.apply(point))
+      .map(|value| Interval::new(value, checked_offset(value, 1)))
+      .collect())
+  }
+
+  // Values of the source whose image is in the interval
+  pub fn preimage(&self, interval: &Interval) -> Interval {
+    let (scale, offset) = (self.scale as i128, self.offset);
+    let (begin, last) = (interval.begin as i128, interval.end as i128 - 1);
+    let (first, last) = match self.scale {
+      0 if begin <= offset && offset <= last => (self.source.begin as i128, self.source.end as i128 - 1),
+      0 => return Interval::new(self.source.begin, self.source.begin),
+      _ if self.scale > 0 => (ceil_div(begin - offset, scale), floor_div(last - offset, scale)),
+      _ => (ceil_div(last - offset, scale), floor_div(begin - offset, scale))
+    };
+
+    let first = first.max(self.source.begin as i128) as i64;
+    let end = (last + 1).min(self.source.end as i128) as i64;
+    Interval::new(first, end.max(first))
+  }
+
+  // Transform of applying this range and then the next one to the values of
+  // part, a single value gets scale 1 and its exact offset because the product
+  // of the scales doesn't matter there (and it can overflow)
+  fn then(&self, next: &MapRange, part: &Interval) -> Result<(i64, i128), String> {
+    let overflow = || format!("Overflow composing {} * x + {} and {} * x + {} in {}",
+      self.scale, self.offset, next.scale, next.offset, part);
+    if part.len() == 1 {
+      let value = (next.scale as i128).checked_mul(self.apply_wide(part.begin))
+        .and_then(|scaled| scaled.checked_add(next.offset))
+        .ok_or_else(overflow)?;
+      return Ok((1, value - part.begin as i128));
+    }
+
+    let offset = (next.scale as i128).checked_mul(self.offset)
+      .and_then(|scaled| scaled.checked_add(next.offset));
+    self.scale.checked_mul(next.scale).zip(offset).ok_or_else(overflow)
+  }
+}
+
+// Map of integers defined by ranges, when two ranges overlap the first one
+// is applied and the values out of every range map to themselves
+#[derive(Clone, Debug, Default)]
+pub struct IntervalMap {
+  pub ranges: Vec<MapRange>
+}
+
+impl IntervalMap {
+  pub fn new() -> IntervalMap {
+    IntervalMap { ranges: Vec::new() }
+  }
+
+  pub fn push_affine(&mut self, source: Interval, scale: i64, offset: i128) {
+    self.ranges.push(MapRange { source, scale, offset });
+  }
+
+  // True when the image of an interval is always an interval (no scale above 1)
+  pub fn keeps_intervals(&self) -> bool {
+    self.ranges.iter().all(|range| range.scale.abs() <= 1)
+  }
+
+  // Image of a single value
+  pub fn get(&self, point: i64) -> i64 {
+    for range in self.ranges.iter() {
+      if range.source.contains(point) {
+        return range.apply(point);
+      }
+    }
+
+    point
+  }
+
+  // Image of a set of values
+  pub fn map_set(&self, set: &IntervalSet) -> Result<IntervalSet, String> {
+    self.normalized().map_set_sorted(set)
+  }
+
+  // Image of a set of values sweeping the intervals of the set and the ranges
+  // at the same time, the map must be normalized
+  pub fn map_set_sorted(&self, set: &IntervalSet) -> Result<IntervalSet, String> {
+    let mut result: Vec<Interval> = Vec::new();
+    let mut first_range = 0usize;
+    for interval in set.intervals() {
+      while first_range < self.ranges.len() && self.ranges[first_range].source.end <= interval.begin {
+        first_range += 1;
+      }
+
+      let mut begin = interval.begin;
+      for range in self.ranges[first_range..].iter() {
+        if range.source.begin >= interval.end {
+          break;
+        }
+
+        if range.source.begin > begin {
+          result.push(Interval::new(begin, range.source.begin));
+          begin = range.source.begin;
+        }
+
+        let end = range.source.end.min(interval.end);
+        result.extend(range.image(&Interval::new(begin, end))?);
+        begin = end;
+      }
+
+      if begin < interval.end {
+        result.push(Interval::new(begin, interval.end));
+      }
+    }
+
+    Ok(IntervalSet::from_intervals(result))
+  }
+
+  // Set of values whose image is in the set
+  pub fn preimage(&self, set: &IntervalSet) -> IntervalSet {
+    let normalized = self.normalized();
+    let mut domain: Vec<Interval> = Vec::new();
+    let mut result: Vec<Interval> = Vec::new();
+    for range in normalized.ranges.iter() {
+      domain.push(range.source);
+      for interval in set.intervals() {
+        result.push(range.preimage(interval));
+      }
+    }
+
+    // The values out of every range are their own image
+    let identity = set.difference(&IntervalSet::from_intervals(domain));
+    result.extend(identity.intervals().iter().cloned());
+
+    IntervalSet::from_intervals(result)
+  }
+
+  // Smallest image of the values of the set, without listing the image
+  pub fn min_image(&self, set: &IntervalSet) -> Option<i64> {
+    let normalized = self.normalized();
+    let mut domain: Vec<Interval> = Vec::new();
+    let mut values: Vec<i64> = Vec::new();
+    for range in normalized.ranges.iter() {
+      domain.push(range.source);
+      for interval in set.intervals() {
+        let part = interval.intersect(&range.source);
+        if !part.is_empty() {
+          values.push(range.apply(part.begin).min(range.apply(part.end - 1)));
+        }
+      }
+    }
+
+    let identity = set.difference(&IntervalSet::from_intervals(domain));
+    values.extend(identity.min());
+    values.into_iter().min()
+  }
+
+  // Equivalent map with sorted and disjoint ranges, without identity ranges
+  // and with the contiguous ranges of the same transform merged
+  pub fn normalized(&self) -> IntervalMap {
+    let mut ranges: Vec<MapRange> = self.ranges.iter()
+      .filter(|range| !range.source.is_empty())
+      .cloned()
+      .collect();
+    ranges.sort_by_key(|range| range.source.begin);
+
+    // With overlapping ranges the first one of the list wins
+    let overlapping = ranges.windows(2).any(|pair| pair[0].source.end > pair[1].source.begin);
+    if overlapping {
+      let mut covered = IntervalSet::new();
+      ranges.clear();
+      for range in self.ranges.iter() {
+        let source = IntervalSet::from_intervals(vec![range.source]);
+        for interval in source.difference(&covered).intervals() {
+          ranges.push(MapRange { source: *interval, ..*range });
+        }
+        covered = covered.union(&source);
+      }
+      ranges.sort_by_key(|range| range.source.begin);
+    }
+    ranges.retain(|range| !range.is_identity());
+
+    let mut merged: Vec<MapRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+      match merged.last_mut() {
+        Some(last) if last.source.end == range.source.begin && (last.scale, last.offset) == (range.scale, range.offset) => {
+          last.source.end = range.source.end;
+        },
+        _ => merged.push(range)
+      }
+    }
+
+    IntervalMap { ranges: merged }
+  }
+
+  // Map equivalent to apply this map and then the next one, an error when a
+  // transform of the result is past the integer limits
+  pub fn compose(&self, next: &IntervalMap) -> Result<IntervalMap, String> {
+    let first = self.normalized();
+    let second = next.normalized();
+    let mut ranges: Vec<MapRange> = Vec::new();
+
+    let mut domain: Vec<Interval> = Vec::new();
+    for range in first.ranges.iter() {
+      domain.push(range.source);
+      let mut rest = IntervalSet::from_intervals(vec![range.source]);
+      for next_range in second.ranges.iter() {
+        let part = range.preimage(&next_range.source);
+        if part.is_empty() {
+          continue;
+        }
+
+        let (scale, offset) = range.then(next_range, &part)?;
+        ranges.push(MapRange { source: part, scale, offset });
+        rest = rest.difference(&IntervalSet::from_intervals(vec![part]));
+      }
+
+      for interval in rest.intervals() {
+        ranges.push(MapRange { source: *interval, ..*range });
+      }
+    }
+
+    // The values out of the first map go straight to the next one
+    let domain = IntervalSet::from_intervals(domain);
+    for next_range in second.ranges.iter() {
+      let source = IntervalSet::from_intervals(vec![next_range.source]);
+      for interval in source.difference(&domain).intervals() {
+        ranges.push(MapRange { source: *interval, ..*next_range });
+      }
+    }
+
+    Ok(IntervalMap { ranges }.normalized())
+  }
+
+  // Ranges in the almanac format, one "destination source length" per line
+  // and the scale at the end when it is not 1 (destination is the smallest
+  // value of the image)
+  pub fn to_almanac(&self) -> String {
+    let mut output = String::new();
+    for range in self.ranges.iter() {
+      let destination = range.apply(range.source.begin).min(range.apply(range.source.end - 1));
+      output += &format!("{} {} {}", destination, range.source.begin, range.source.len());
+      if range.scale != 1 {
+        output += &format!(" {}", range.scale);
+      }
+      output += "\n";
+    }
+
+    output
+  }
+}
@@ -1,115 +1,50 @@
-use std::collections::LinkedList;
+mod almanac;
+mod interval_map;
+
+use almanac::Map;
+use interval_map::{Interval, IntervalSet};
 use std::env;
 use std::fs;
-use std::usize;
-
-#[derive(Clone, Debug)]
-struct Range {
-  origin: u64,
-  destination: u64,
-  length: u64
-}
 
-#[derive(Clone, Debug)]
-struct Map {
-  id: usize,
-  ranges: LinkedList<Range>
-}
-
-fn in_range(origin: u64, range: &Range) -> bool {
-  origin >= range.origin && origin < (range.origin + range.length)
-}
-
-fn get_destination(origin: u64, map: &Map) -> u64 {
-  for range in map.ranges.iter() {
-    if !in_range(origin, range) {
-      continue;
-    }
-    
-    let offset = origin - range.origin;
-    return range.destination + offset;
-  }
-  
-  origin
-}
-
-fn parse_range(line: &String) -> Range {
-  let mut split = line.split(' ');
-  let destination: u64 = split.next().unwrap().parse().unwrap();
-  let origin: u64 = split.next().unwrap().parse().unwrap();
-  let length: u64 = split.next().unwrap().parse().unwrap();
-  
-  Range { origin, destination, length }
-}
-
-fn parse_input(input: &String) -> (Vec<u64>, Vec<Map>) {
+fn parse_seeds(line: &str) -> Result<Vec<u64>, String> {
   let mut seeds: Vec<u64> = Vec::new();
-  let mut maps: Vec<Map> = Vec::new();
-
-  let mut map = Map {
-    id: 0,
-    ranges: LinkedList::new()
-  };
-  for line in input.lines() {
-    if line.is_empty() {
+  let mut first = true;
+  for token in line.split(' ') {
+    if token.is_empty() {
       continue;
     }
-
-    if seeds.is_empty() {
-      let mut first = true;
-      for token in line.split(' ') {
-        if token.is_empty() {
-          continue;
-        }
-        
-        if first {
-          first = false;
-          continue;
-        }
-
-        let seed: u64 = token.parse().unwrap();
-        seeds.push(seed);
-      }
+    
+    if first {
+      first = false;
       continue;
     }
 
-    if !line.contains("map:") {
-      map.ranges.push_back(parse_range(&line.to_string()));
-    } else if map.id != 0 {
-      maps.push(map.clone());
-      map.ranges.clear();
-      map.id += 1;
-    } else {
-      map.id += 1;
+    // The seed is kept as the interval [seed, seed + 1)
+    let seed: u64 = token.parse().map_err(|_| format!("Invalid seed '{}'", token))?;
+    if seed >= i64::MAX as u64 {
+      return Err(format!("Seed {} past the integer limit {}", seed, i64::MAX - 1));
     }
-
+    seeds.push(seed);
   }
 
-  maps.push(map);
-
-  (seeds, maps)
+  Ok(seeds)
 }
 
-fn print_maps(maps: &Vec<Map>) {
-  for map in maps {
-    println!("Id {}:", map.id);
-    for range in map.ranges.iter() {
-      println!("{:?}", range);
-    }
-  }
+fn parse_input(input: &str) -> Result<(Vec<u64>, Vec<Map>), String> {
+  let seeds_line = input.lines().find(|line| !line.is_empty()).unwrap_or("");
+  let seeds = parse_seeds(seeds_line)?;
+  let maps = almanac::parse_maps(input)?;
+
+  Ok((seeds, maps))
 }
 
-fn get_min_location(seeds: &Vec<u64>, maps: &Vec<Map>) -> u64 {
-  let mut result: Vec<u64> = seeds.clone();
+fn print_maps(maps: &[Map]) {
   for map in maps {
-    let mut destinations: Vec<u64> = Vec::new();
-    for origin in result {
-      destinations.push(get_destination(origin, map));
+    println!("{}-to-{} map:", map.source, map.destination);
+    for range in map.ranges.ranges.iter() {
+      println!("{:?}", range);
     }
-    result = destinations;
   }
-
-  result.iter().min().unwrap().clone()
 }
 
 fn main() {
@@ -123,8 +58,38 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
 
-  let (seeds, maps) = parse_input(&input);
+  let (from, to, options) = almanac::parse_arguments(&args);
+  if almanac::run_input_command(&input, &from, &to, &options) {
+    return;
+  }
+
+  let (seeds, maps) = match parse_input(&input) {
+    Ok(almanac) => almanac,
+    Err(error) => {
+      println!("Error: {}", error);
+      return;
+    }
+  };
+
+  let path = match almanac::find_path(&maps, &from, &to) {
+    Ok(path) => path,
+    Err(error) => {
+      println!("Error: {}", error);
+      return;
+    }
+  };
+
+  let seeds_set = IntervalSet::from_intervals(
+    seeds.iter().map(|seed| Interval::new(*seed as i64, *seed as i64 + 1)).collect()
+  );
+  if almanac::run_path_command(&input, &seeds_set, &path, &from, &to, &options) {
+    return;
+  }
+
   println!("{:?}", seeds);
   print_maps(&maps);
-  println!("Min location {}", get_min_location(&seeds, &maps));
+  match almanac::min_destination(&seeds_set, &path) {
+    Ok(min) => println!("Min {} {}", to, min),
+    Err(error) => println!("Error: {}", error)
+  }
 }
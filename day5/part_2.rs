@@ -1,103 +1,15 @@
-use std::collections::HashSet;
-use std::collections::LinkedList;
+mod almanac;
+mod interval_map;
+
+use almanac::Map;
+use interval_map::{Interval, IntervalSet};
 use std::env;
 use std::fs;
-use std::usize;
-
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-struct SubRange {
-  begin: i64,
-  end: i64,
-  offset: i64
-}
-
-#[derive(Clone, Debug)]
-struct Map {
-  id: usize,
-  ranges: LinkedList<SubRange>
-}
-
-fn in_range(range: &SubRange, sub_range: &SubRange) -> bool {
-  let intersection = intersect_range(range, sub_range);
-
-  intersection.begin < intersection.end
-}
-
-fn apply_offset(range: &SubRange) -> SubRange {
-  SubRange {
-    begin: range.begin + range.offset,
-    end: range.end + range.offset,
-    offset: 0
-  }
-}
-
-fn intersect_range(range: &SubRange, sub_range: &SubRange) -> SubRange {
-  SubRange {
-    begin: range.begin.max(sub_range.begin),
-    end: range.end.min(sub_range.end),
-    offset: sub_range.offset
-  }
-}
-
-fn exclusion_range(range: &SubRange, sub_range: &SubRange) -> LinkedList<SubRange> {
-  let mut exclusion: LinkedList<SubRange> = LinkedList::new();
-
-  if range.begin < sub_range.begin{
-    exclusion.push_back(SubRange { begin: range.begin, end: sub_range.begin, offset: 0 });
-  }
-
-  if range.end > sub_range.end {
-    exclusion.push_back(SubRange { begin: sub_range.end, end: range.end, offset: 0 });
-  }
-
-  exclusion
-}
-
-fn get_destination(range: &SubRange, map: &Map) -> HashSet<SubRange> {
-  let mut result_set: HashSet<SubRange> = HashSet::new();
-
-  for sub_range in map.ranges.iter() {
-    if !in_range(range, sub_range) {
-      continue;
-    }
-
-    result_set.insert(intersect_range(range, sub_range));
-
-    let exclusions = exclusion_range(range, sub_range);
-    for exclusion in exclusions {
-      let result_part = get_destination(&exclusion, map);
-      result_set.extend(result_part);
-    }
-  }
-
-  if result_set.is_empty() {
-    result_set.insert(range.clone());
-  }
-
-  result_set
-}
-
-fn parse_destination(ranges: HashSet<SubRange>) -> Vec<SubRange> {
-  ranges.iter().map(|e| { apply_offset(e) }).collect()
-}
 
 // Input parsing
 
-fn parse_range(line: &String) -> SubRange {
-  let mut split = line.split(' ');
-  let destination: i64 = split.next().unwrap().parse().unwrap();
-  let origin: i64 = split.next().unwrap().parse().unwrap();
-  let length: i64 = split.next().unwrap().parse().unwrap();
-  
-  SubRange {
-    begin: origin,
-    end: origin + length,
-    offset: destination - origin
-  }
-}
-
-fn parse_input_ranges(line: &String) -> Vec<SubRange> {
-  let mut input: Vec<SubRange> = Vec::new();
+fn parse_input_ranges(line: &str) -> Result<IntervalSet, String> {
+  let mut input: Vec<Interval> = Vec::new();
   let mut first = true;
   let mut length = false;
   let mut offset = 0i64;
@@ -108,71 +20,36 @@ fn parse_input_ranges(line: &String) -> Vec<SubRange> {
       continue;
     }
 
+    let value: i128 = token.parse().map_err(|_| format!("Invalid seed value '{}'", token))?;
+    if value < 0 || value > i64::MAX as i128 {
+      return Err(format!("Seed value {} out of the integer limits", value));
+    }
+    let value = value as i64;
+
     if !length {
-      offset = token.parse().unwrap();
+      offset = value;
       length = true;
     } else {
       length = false;
-      let num: i64 = token.parse().unwrap();
-      input.push(SubRange { begin: offset, end: offset + num, offset: 0 });
+      let end = offset.checked_add(value)
+        .ok_or(format!("Seed range {} + {} past the integer limit", offset, value))?;
+      input.push(Interval::new(offset, end));
     }
   }
 
-  input
-}
-
-fn parse_input(input: &String) -> (Vec<SubRange>, Vec<Map>) {
-  let mut seeds: Vec<SubRange> = Vec::new();
-  let mut maps: Vec<Map> = Vec::new();
-
-  let mut map = Map {
-    id: 0,
-    ranges: LinkedList::new()
-  };
-  for line in input.lines() {
-    if line.is_empty() {
-      continue;
-    }
-
-    if seeds.is_empty() {
-      seeds = parse_input_ranges(&line.to_string());
-      continue;
-    }
-
-    if !line.contains("map:") {
-      map.ranges.push_back(parse_range(&line.to_string()));
-    } else if map.id != 0 {
-      maps.push(map.clone());
-      map.ranges.clear();
-      map.id += 1;
-    } else {
-      map.id += 1;
-    }
-
+  if length {
+    return Err(format!("Seed range {} without a length", offset));
   }
 
-  maps.push(map);
-
-  (seeds, maps)
+  Ok(IntervalSet::from_intervals(input))
 }
 
-fn get_min_location(seeds: &Vec<SubRange>, maps: &Vec<Map>) -> i64 {
-  let mut result: Vec<SubRange> = seeds.clone();
-  for map in maps {
-    let mut destinations: Vec<SubRange> = Vec::new();
-    for origin in result {
-      destinations.extend(parse_destination(get_destination(&origin, map)));
-    }
-    result = destinations;
-  }
+fn parse_input(input: &str) -> Result<(IntervalSet, Vec<Map>), String> {
+  let seeds_line = input.lines().find(|line| !line.is_empty()).unwrap_or("");
+  let seeds = parse_input_ranges(seeds_line)?;
+  let maps = almanac::parse_maps(input)?;
 
-  result.into_iter().reduce(|acc, e| {
-    if acc.begin <= e.begin {
-      acc
-    } else {
-      e
-    }
-  }).unwrap().begin
+  Ok((seeds, maps))
 }
 
 fn main() {
@@ -186,6 +63,32 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
 
-  let (seeds, maps) = parse_input(&input);
-  println!("\n\nMin location {}", get_min_location(&seeds, &maps));
+  let (from, to, options) = almanac::parse_arguments(&args);
+  if almanac::run_input_command(&input, &from, &to, &options) {
+    return;
+  }
+
+  let (seeds, maps) = match parse_input(&input) {
+    Ok(almanac) => almanac,
+    Err(error) => {
+      println!("Error: {}", error);
+      return;
+    }
+  };
+
+  let path = match almanac::find_path(&maps, &from, &to) {
+    Ok(path) => path,
+    Err(error) => {
+      println!("Error: {}", error);
+      return;
+    }
+  };
+  if almanac::run_path_command(&input, &seeds, &path, &from, &to, &options) {
+    return;
+  }
+
+  match almanac::min_destination(&seeds, &path) {
+    Ok(min) => println!("\n\nMin {} {}", to, min),
+    Err(error) => println!("Error: {}", error)
+  }
 }
@@ -14,7 +14,47 @@ fn calculate_distance(time: u64, boot_time: u64) -> u64 {
   travel_time * speed
 }
 
+// Integer square root (largest r with r * r <= n) with Newton's method
+fn isqrt(n: u128) -> u128 {
+  if n < 2 {
+    return n;
+  }
+
+  let mut root = n;
+  let mut next = (root + n / root) / 2;
+  while next < root {
+    root = next;
+    next = (root + n / root) / 2;
+  }
+
+  root
+}
+
+// The hold times h that win are the ones with h * (time - h) > distance, an
+// interval around time / 2 bounded by the roots of h^2 - time * h + distance.
+// The lowest one is found with an integer square root and fixed with exact
+// products, the interval is symmetric so the highest one is time - lowest
 fn n_of_wins(record: &Record) -> u64 {
+  let time = record.time as u128;
+  let distance = record.distance as u128;
+  let wins = |hold: u128| hold * (time - hold) > distance;
+  if time * time <= 4 * distance {
+    return 0;
+  }
+
+  let mut lowest = (time - isqrt(time * time - 4 * distance)) / 2;
+  while lowest > 0 && wins(lowest - 1) {
+    lowest -= 1;
+  }
+  while lowest <= time / 2 && !wins(lowest) {
+    lowest += 1;
+  }
+
+  if lowest > time / 2 { 0 } else { (time - 2 * lowest + 1) as u64 }
+}
+
+// Tries every hold time, used to check the closed form
+fn n_of_wins_brute_force(record: &Record) -> u64 {
   let mut n_wins = 0u64;
 
   for boot_time in 1..record.time {
@@ -73,6 +113,25 @@ fn main() {
   let input = fs::read_to_string(file_name)
     .expect("Error reading the file");
   let records = parse_input(input.as_str());
+
+  // "--cross-check" compares the closed form with trying every hold time
+  if args.len() > 2 && args[2] == "--cross-check" {
+    let mut mismatches = 0usize;
+    for record in records.iter() {
+      let (wins, brute_force_wins) = (n_of_wins(record), n_of_wins_brute_force(record));
+      if wins != brute_force_wins {
+        println!("{:?}\nMismatch: closed form {}, brute force {}", record, wins, brute_force_wins);
+        mismatches += 1;
+      }
+    }
+
+    println!("{} of {} races match", records.len() - mismatches, records.len());
+    if mismatches > 0 {
+      std::process::exit(1);
+    }
+    return;
+  }
+
   let mut error = 1u64;
   for record in records {
     let wins = n_of_wins(&record);